@@ -0,0 +1,278 @@
+// client subcommands, they talk to the running daemon over the control socket
+// ex: hyprland-per-window-layout set 1 --class
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use crate::single::SingleInstance;
+use hyprland_per_window_layout::hyprctl::Hyprctl;
+use hyprland_per_window_layout::layouts::KbLayouts;
+use hyprland_per_window_layout::options::options_path;
+use hyprland_per_window_layout::validate::{check_options, has_errors};
+
+const USAGE: &str = "Usage: hyprland-per-window-layout [ctl] <command>
+
+Without a command the daemon is started.
+
+Commands:
+  status                            show active window, layout and keyboards
+  list                              list known windows and their layouts
+  set <layout> [--class [CLASS]]    set layout for the active window,
+               [--address 0x..]     for a window, or for all windows of a class
+                                    (the class of the active window if CLASS is omitted)
+  forget [--class [CLASS]]          forget layout of the active window,
+         [--address 0x..]           of a window, or of a class
+  reload                            reload options.toml
+  dump-state                        print everything the daemon knows
+  check-config [FILE]               check options.toml for errors
+  help                              show this help
+
+<layout> is an index in kb_layout or a layout name, ex: 1, us, \"English (US)\"";
+
+// run client command and return process exit code
+pub fn run(instance_name: &str, args: &[String]) -> i32 {
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    // "ctl" prefix is optional
+    let args = match args.first() {
+        Some(&"ctl") => &args[1..],
+        _ => &args[..],
+    };
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (*command, rest),
+        None => {
+            println!("{USAGE}");
+            return 2;
+        }
+    };
+    // works without the daemon
+    if command == "check-config" {
+        return match rest {
+            [] => check_config(options_path()),
+            [path] => check_config(PathBuf::from(path)),
+            _ => {
+                eprintln!("check-config: unexpected arguments\n\n{USAGE}");
+                2
+            }
+        };
+    }
+    let request = match build_request(instance_name, command, rest) {
+        Ok(Some(request)) => request,
+        Ok(None) => {
+            println!("{USAGE}");
+            return 0;
+        }
+        Err(e) => {
+            eprintln!("{e}\n\n{USAGE}");
+            return 2;
+        }
+    };
+    match send(instance_name, &request) {
+        Ok(data) => {
+            if !data.is_null() {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&data).unwrap_or(data.to_string())
+                );
+            }
+            0
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            1
+        }
+    }
+}
+
+// print problems found in options.toml, exit code is 1 if there are errors
+fn check_config(path: PathBuf) -> i32 {
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("Error reading {}: {e}", path.display());
+            return 1;
+        }
+    };
+    // layouts are checked only if Hyprland is running
+    let kb = match Hyprctl::resolve() {
+        Ok(hyprctl) => KbLayouts::fetch(&hyprctl),
+        Err(_) => KbLayouts::default(),
+    };
+    if kb.names.is_empty() {
+        println!("Hyprland layouts are not available, layout names and indices are not checked");
+    }
+    let problems = check_options(&content, &kb, &[]);
+    for problem in problems.iter() {
+        println!("{}:{problem}", path.display());
+    }
+    if has_errors(&problems) {
+        1
+    } else {
+        println!("{}: OK", path.display());
+        0
+    }
+}
+
+// turn command line into a control socket request, None for help
+fn build_request(
+    instance_name: &str,
+    command: &str,
+    args: &[&str],
+) -> Result<Option<Value>, String> {
+    let request = match command {
+        "status" => json!({ "command": "status" }),
+        "list" => json!({ "command": "windows" }),
+        "reload" => json!({ "command": "reload" }),
+        "dump-state" => json!({ "command": "dump_state" }),
+        "set" => {
+            let (layout, args) = match args.split_first() {
+                Some((layout, args)) => (*layout, args),
+                None => return Err("set: layout is required".to_string()),
+            };
+            // numbers are indices, anything else is a layout name
+            let layout = match layout.parse::<u16>() {
+                Ok(index) => json!(index),
+                Err(_) => json!(layout),
+            };
+            let mut request = target(instance_name, args)?;
+            request["command"] = json!("set_layout");
+            request["layout"] = layout;
+            request
+        }
+        "forget" => {
+            let mut request = target(instance_name, args)?;
+            request["command"] = json!("forget");
+            request
+        }
+        "help" | "--help" | "-h" => return Ok(None),
+        _ => return Err(format!("Unknown command: {command}")),
+    };
+    if !matches!(command, "set" | "forget") && !args.is_empty() {
+        return Err(format!(
+            "{command}: unexpected arguments: {}",
+            args.join(" ")
+        ));
+    }
+    Ok(Some(request))
+}
+
+// parse --class [CLASS] or --address 0x.. options
+fn target(instance_name: &str, args: &[&str]) -> Result<Value, String> {
+    let mut request = json!({});
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).filter(|v| !v.starts_with("--"));
+        match args[i] {
+            "--class" => {
+                let class = match value {
+                    Some(class) => class.to_string(),
+                    None => active_class(instance_name)?,
+                };
+                request["class"] = json!(class);
+            }
+            "--address" => match value {
+                Some(address) => request["address"] = json!(address),
+                None => return Err("--address: value is required".to_string()),
+            },
+            arg => return Err(format!("Unknown option: {arg}")),
+        }
+        i += if value.is_some() { 2 } else { 1 };
+    }
+    if !request["class"].is_null() && !request["address"].is_null() {
+        return Err("use either --class or --address".to_string());
+    }
+    Ok(request)
+}
+
+// class of the active window, to pin the current app
+fn active_class(instance_name: &str) -> Result<String, String> {
+    let status = send(instance_name, &json!({ "command": "status" }))?;
+    match status["active_class"].as_str() {
+        Some(class) if !class.is_empty() => Ok(class.to_string()),
+        _ => Err("no active window".to_string()),
+    }
+}
+
+// send one request and wait for the reply
+fn send(instance_name: &str, request: &Value) -> Result<Value, String> {
+    let mut stream = SingleInstance::connect(instance_name)
+        .map_err(|e| format!("the daemon is not running ({e})"))?;
+    writeln!(stream, "{request}").map_err(|e| e.to_string())?;
+    let mut line = String::new();
+    BufReader::new(stream)
+        .read_line(&mut line)
+        .map_err(|e| e.to_string())?;
+    let reply: Value =
+        serde_json::from_str(&line).map_err(|e| format!("bad reply from the daemon: {e}"))?;
+    if reply["ok"].as_bool() == Some(true) {
+        Ok(reply["data"].clone())
+    } else {
+        Err(reply["error"]
+            .as_str()
+            .unwrap_or("unknown error")
+            .to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // no daemon listens on this name, so --class without a value fails to get the active class
+    const NO_DAEMON: &str = "hyprland-per-window-layout-cli-test";
+
+    fn request(line: &str) -> Result<Option<Value>, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        build_request(NO_DAEMON, args[0], &args[1..])
+    }
+
+    #[test]
+    fn arguments_are_parsed_into_requests() {
+        for (line, expected) in [
+            ("status", json!({ "command": "status" })),
+            ("list", json!({ "command": "windows" })),
+            (
+                "set 1 --class firefox",
+                json!({ "command": "set_layout", "layout": 1, "class": "firefox" }),
+            ),
+            (
+                "set de --address 0x1",
+                json!({ "command": "set_layout", "layout": "de", "address": "0x1" }),
+            ),
+            ("forget", json!({ "command": "forget" })),
+        ] {
+            assert_eq!(request(line), Ok(Some(expected)), "{line}");
+        }
+        assert_eq!(request("help"), Ok(None));
+    }
+
+    #[test]
+    fn bad_arguments_are_errors() {
+        for (line, error) in [
+            ("set", "set: layout is required"),
+            ("set de --address", "--address: value is required"),
+            (
+                "set de --address --class firefox",
+                "--address: value is required",
+            ),
+            (
+                "set de --class firefox --address 0x1",
+                "use either --class or --address",
+            ),
+            ("forget --title kitty", "Unknown option: --title"),
+            ("status now", "status: unexpected arguments: now"),
+            ("reload -f", "reload: unexpected arguments: -f"),
+            ("restart", "Unknown command: restart"),
+        ] {
+            assert_eq!(request(line), Err(error.to_string()), "{line}");
+        }
+    }
+
+    #[test]
+    fn class_without_value_is_asked_from_the_daemon() {
+        let error = request("set de --class").unwrap_err();
+        assert!(error.starts_with("the daemon is not running"), "{error}");
+        let error = request("set de --class --address 0x1").unwrap_err();
+        assert!(error.starts_with("the daemon is not running"), "{error}");
+    }
+}
@@ -0,0 +1,108 @@
+// control socket protocol: one json request per line, one json reply per line
+// requests:
+// {"command": "status"}
+// {"command": "windows"}
+// {"command": "keyboards"}
+// {"command": "layouts"}
+// {"command": "set_layout", "layout": 1, "class": "org.telegram.desktop"}
+// {"command": "set_layout", "layout": "us", "address": "0x5632c5a16e80"}
+// {"command": "forget", "address": "0x5632c5a16e80"}
+// {"command": "reload"}
+// {"command": "dump_state"}
+// replies:
+// {"ok": true, "data": ...}
+// {"ok": false, "error": "..."}
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use hyprland_per_window_layout::daemon::Daemon;
+use hyprland_per_window_layout::layouts::LayoutRef;
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum LayoutArg {
+    Index(u16),
+    Name(String),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum Request {
+    Status,
+    Windows,
+    Keyboards,
+    Layouts,
+    SetLayout {
+        layout: LayoutArg,
+        address: Option<String>,
+        class: Option<String>,
+    },
+    Forget {
+        address: Option<String>,
+        class: Option<String>,
+    },
+    Reload,
+    DumpState,
+}
+
+// handle one request line, returns reply line
+pub fn handle(daemon: &Daemon, line: &str) -> String {
+    log::debug!("Control request: {}", line);
+    let reply = match serde_json::from_str::<Request>(line) {
+        Ok(request) => match execute(daemon, request) {
+            Ok(data) => json!({ "ok": true, "data": data }),
+            Err(e) => json!({ "ok": false, "error": e }),
+        },
+        Err(e) => json!({ "ok": false, "error": format!("bad request: {e}") }),
+    };
+    reply.to_string()
+}
+
+fn execute(daemon: &Daemon, request: Request) -> Result<Value, String> {
+    match request {
+        Request::Status => Ok(daemon.status_json()),
+        Request::Windows => Ok(daemon.windows_json()),
+        Request::Keyboards => Ok(daemon.status_json()["keyboards"].clone()),
+        Request::Layouts => {
+            let status = daemon.status_json();
+            Ok(json!({
+                "kb_layouts": status["kb_layouts"],
+                "layouts": status["layouts"],
+            }))
+        }
+        Request::SetLayout {
+            layout,
+            address,
+            class,
+        } => {
+            let layout = match layout {
+                LayoutArg::Index(index) => LayoutRef::Index(index),
+                LayoutArg::Name(name) => LayoutRef::from_key(&name),
+            };
+            let index = daemon.resolve_layout(&layout)?;
+            let address = address.map(normalize_address);
+            daemon.set_layout(address.as_deref(), class.as_deref(), index)?;
+            Ok(json!(index))
+        }
+        Request::Forget { address, class } => {
+            let address = address.map(normalize_address);
+            daemon.forget(address.as_deref(), class.as_deref())?;
+            Ok(Value::Null)
+        }
+        Request::Reload => {
+            daemon.reload_options()?;
+            Ok(Value::Null)
+        }
+        Request::DumpState => Ok(daemon.dump_json()),
+    }
+}
+
+// window addresses are stored with 0x prefix
+fn normalize_address(address: String) -> String {
+    if address.starts_with("0x") {
+        address
+    } else {
+        format!("0x{address}")
+    }
+}
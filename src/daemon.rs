@@ -0,0 +1,397 @@
+//! work with messages from hyprland socket
+//! events are handled by [`State`], its actions are performed here
+
+use serde_json::Value;
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// messages from Hyprland socket
+use crate::events::HyprEvent;
+
+// options struct
+use crate::options::{read_options, try_read_options};
+
+// layout names
+use crate::layouts::{KbLayouts, KeyboardInfo, LayoutRef};
+
+// windows info
+use crate::rules::{MonitorInfo, WindowInfo};
+
+// daemon state machine
+use crate::state::{Action, Event, ProcessLookups, State};
+
+// learned layouts saved between restarts
+use crate::state_file::{load_state, save_state};
+
+// parent processes of windows
+use crate::process::{foreground_process, parent_pid};
+
+// requests to Hyprland socket
+use crate::hyprctl::{Hyprctl, HyprctlError};
+
+/// the state shared by the event loop, control socket, options watcher and signal handler
+/// cloning is cheap, all clones share the same state
+#[derive(Clone)]
+pub struct Daemon {
+    state: Arc<Mutex<State>>,
+    // Hyprland instance we are connected to, changes when Hyprland is restarted
+    hyprctl: Arc<Mutex<Option<Hyprctl>>>,
+}
+
+impl Default for Daemon {
+    fn default() -> Self {
+        Daemon::new()
+    }
+}
+
+impl Daemon {
+    /// daemon with empty state, call [`Daemon::listen`] to start it
+    pub fn new() -> Daemon {
+        Daemon {
+            state: Arc::new(Mutex::new(State::new())),
+            hyprctl: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // state is still usable if another thread panicked
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Hyprland instance to talk to, requests are skipped until it is set
+    pub fn set_instance(&self, hyprctl: Hyprctl) {
+        *self.hyprctl.lock().unwrap_or_else(|e| e.into_inner()) = Some(hyprctl);
+    }
+
+    fn hyprctl(&self) -> Option<Hyprctl> {
+        self.hyprctl
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// listen event socket of the instance until it is closed
+    /// the instance should be set and options loaded before, see [`Daemon::set_instance`],
+    /// learned layouts are loaded first, then windows that are already open are synced
+    pub fn listen(&self) -> std::io::Result<()> {
+        let hyprctl = self.hyprctl().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotConnected, "Hyprland is not running")
+        })?;
+        let stream = match UnixStream::connect(hyprctl.event_socket()) {
+            Ok(stream) => stream,
+            Err(e) => {
+                println!("Couldn't connect: {e:?}");
+                return Err(e);
+            }
+        };
+        let mut reader = BufReader::new(stream);
+        self.load_learned();
+        self.refresh_keyboards();
+        if let Some(monitors) = fetch_monitors(&hyprctl) {
+            self.handle(Event::Monitors(monitors));
+        }
+        self.sync_clients(&hyprctl);
+        let result = loop {
+            // read message from socket
+            let mut buf: Vec<u8> = vec![];
+            let readed = match reader.read_until(b'\n', &mut buf) {
+                Ok(size) => size,
+                Err(e) => {
+                    log::warn!("Error reading from socket: {}", e);
+                    break Err(e);
+                }
+            };
+            if readed == 0 {
+                break Ok(());
+            }
+            let line = String::from_utf8_lossy(&buf);
+            log::debug!("E:'{}'", line.trim_end());
+            if let Some(event) = HyprEvent::parse_line(&line) {
+                self.handle(event.into());
+            }
+        };
+        self.save_learned();
+        result
+    }
+
+    // windows opened before we started or while we were disconnected
+    fn sync_clients(&self, hyprctl: &Hyprctl) {
+        let json = match hyprctl.request_json("clients") {
+            Ok(json) => json,
+            Err(e) => {
+                log::warn!("Failed to get clients: {}", e);
+                return;
+            }
+        };
+        let clients = match json.as_array() {
+            Some(clients) => clients,
+            None => return,
+        };
+        let lookups = self.lock().process_lookups();
+        let mut windows = Vec::new();
+        let mut focused = None;
+        for client in clients {
+            let addr = match client["address"].as_str() {
+                Some(addr) => addr.to_string(),
+                None => continue,
+            };
+            // the focused window is the first in focus history
+            // processes of others are checked when they are focused
+            let is_focused = client["focusHistoryID"].as_i64() == Some(0);
+            if is_focused {
+                focused = Some(addr.clone());
+            }
+            let lookups = ProcessLookups {
+                process: lookups.process && is_focused,
+                ..lookups
+            };
+            windows.push((addr, window_info(client, lookups)));
+        }
+        self.handle(Event::Clients { windows, focused });
+    }
+
+    /// handle an event and perform the resulting actions
+    pub fn handle(&self, event: Event) {
+        let actions = self.lock().handle(event);
+        self.perform(actions);
+    }
+
+    // actions are performed without holding the lock
+    // their results are fed back as events
+    fn perform(&self, actions: Vec<Action>) {
+        let hyprctl = match self.hyprctl() {
+            Some(hyprctl) => hyprctl,
+            None => return,
+        };
+        for action in actions {
+            match action {
+                Action::SwitchLayout { keyboards, .. } => {
+                    for keyboard in change_layout(&hyprctl, &keyboards) {
+                        self.handle(Event::KeyboardRejected(keyboard));
+                    }
+                }
+                Action::FetchWindow(addr) => {
+                    let lookups = self.lock().process_lookups();
+                    let info = fetch_window_info(&hyprctl, &addr, lookups);
+                    self.handle(Event::WindowFetched { addr, info });
+                }
+                Action::SaveLearned => self.save_learned(),
+                Action::FetchKeyboards => self.refresh_keyboards(),
+                Action::FetchProcess { addr, pid } => {
+                    let process = foreground_process(pid).unwrap_or_default();
+                    self.handle(Event::ProcessFetched { addr, process });
+                }
+            }
+        }
+    }
+
+    /// list connected keyboards, new ones are added and unplugged ones removed
+    pub fn refresh_keyboards(&self) {
+        if let Some(keyboards) = self.hyprctl().as_ref().and_then(fetch_keyboards) {
+            self.handle(Event::Keyboards(keyboards));
+        }
+    }
+
+    /// read options.toml, resolve layout names and make them current
+    /// problems are printed, defaults are used for broken parts
+    /// layouts are read from the instance, see [`Daemon::set_instance`]
+    pub fn load_options(&self) {
+        let kb_layouts = match self.hyprctl() {
+            Some(hyprctl) => KbLayouts::fetch(&hyprctl),
+            None => KbLayouts::default(),
+        };
+        let mut state = self.lock();
+        let options = read_options(&kb_layouts, state.seen_layouts());
+        state.set_options(options, kb_layouts);
+    }
+
+    /// read options.toml again, current options are kept if the file has errors
+    /// learned layouts are not touched
+    pub fn reload_options(&self) -> Result<(), String> {
+        let hyprctl = self
+            .hyprctl()
+            .ok_or_else(|| HyprctlError::NoInstance.to_string())?;
+        let kb_layouts = KbLayouts::fetch(&hyprctl);
+        let mut state = self.lock();
+        let options = try_read_options(&kb_layouts, state.seen_layouts())?;
+        state.set_options(options, kb_layouts);
+        log::info!("Options reloaded");
+        Ok(())
+    }
+
+    /// read layouts learned in previous sessions, only once and only if `persist` is on
+    pub fn load_learned(&self) {
+        let mut state = self.lock();
+        if state.options().persist && state.learned().is_none() {
+            state.set_learned(load_state());
+        }
+    }
+
+    /// write learned layouts to the state file
+    pub fn save_learned(&self) {
+        // write a copy, so events are not blocked by disk
+        let learned = self.lock().learned().cloned();
+        if let Some(learned) = learned {
+            if let Err(e) = save_state(&learned) {
+                log::warn!("Failed to save state: {}", e);
+            }
+        }
+    }
+
+    /// set current layout by its long name, ex: from `active_keymap` of a keyboard
+    pub fn set_active_keymap(&self, long_name: &str) {
+        self.lock().set_active_keymap(long_name);
+    }
+
+    /// find layout index by number or name
+    pub fn resolve_layout(&self, layout: &LayoutRef) -> Result<u16, String> {
+        self.lock().resolve_layout(layout)
+    }
+
+    /// set layout for a window or all windows of a class, see [`State::set_layout`]
+    pub fn set_layout(
+        &self,
+        address: Option<&str>,
+        class: Option<&str>,
+        index: u16,
+    ) -> Result<(), String> {
+        let actions = self.lock().set_layout(address, class, index)?;
+        self.perform(actions);
+        Ok(())
+    }
+
+    /// forget layout of a window or all windows of a class, see [`State::forget`]
+    pub fn forget(&self, address: Option<&str>, class: Option<&str>) -> Result<(), String> {
+        let actions = self.lock().forget(address, class)?;
+        self.perform(actions);
+        Ok(())
+    }
+
+    /// active window, layout and keyboards, see [`State::status_json`]
+    pub fn status_json(&self) -> Value {
+        self.lock().status_json()
+    }
+
+    /// known windows and their layouts, see [`State::windows_json`]
+    pub fn windows_json(&self) -> Value {
+        self.lock().windows_json()
+    }
+
+    /// everything the daemon knows, see [`State::dump_json`]
+    pub fn dump_json(&self) -> Value {
+        self.lock().dump_json()
+    }
+}
+
+// get class and titles of the window from "j/clients" request
+fn fetch_window_info(hyprctl: &Hyprctl, addr: &str, lookups: ProcessLookups) -> Option<WindowInfo> {
+    let json = match hyprctl.request_json("clients") {
+        Ok(json) => json,
+        Err(e) => {
+            log::warn!("{}", e);
+            return None;
+        }
+    };
+    let client = json
+        .as_array()?
+        .iter()
+        .find(|client| client["address"].as_str() == Some(addr))?;
+    Some(window_info(client, lookups))
+}
+
+// class, titles and process from an item of "j/clients" reply
+// /proc is read only for the lookups that are needed
+fn window_info(client: &Value, lookups: ProcessLookups) -> WindowInfo {
+    let field = |name: &str| client[name].as_str().unwrap_or("").to_string();
+    let pid = client["pid"]
+        .as_i64()
+        .and_then(|pid| i32::try_from(pid).ok())
+        .filter(|pid| *pid > 0);
+    WindowInfo {
+        class: field("class"),
+        title: field("title"),
+        initial_class: field("initialClass"),
+        initial_title: field("initialTitle"),
+        pid,
+        parent_pid: pid.filter(|_| lookups.parent_pid).and_then(parent_pid),
+        process: pid
+            .filter(|_| lookups.process)
+            .and_then(foreground_process)
+            .unwrap_or_default(),
+    }
+}
+
+// get keyboards and their layouts from "j/devices" request
+fn fetch_keyboards(hyprctl: &Hyprctl) -> Option<Vec<KeyboardInfo>> {
+    let json = match hyprctl.request_json("devices") {
+        Ok(json) => json,
+        Err(e) => {
+            // Hyprland may be restarting, we'll try again
+            log::debug!("Failed to get devices: {}", e);
+            return None;
+        }
+    };
+    let keyboards = json["keyboards"]
+        .as_array()?
+        .iter()
+        .filter_map(|keyboard| {
+            let name = keyboard["name"].as_str()?;
+            let layout = keyboard["layout"].as_str().unwrap_or("");
+            let variant = keyboard["variant"].as_str().unwrap_or("");
+            Some(KeyboardInfo::new(name, layout, variant))
+        })
+        .collect();
+    Some(keyboards)
+}
+
+// get names and descriptions of monitors from "j/monitors" request
+fn fetch_monitors(hyprctl: &Hyprctl) -> Option<Vec<MonitorInfo>> {
+    let json = match hyprctl.request_json("monitors") {
+        Ok(json) => json,
+        Err(e) => {
+            log::warn!("Failed to get monitors: {}", e);
+            return None;
+        }
+    };
+    let monitors = json
+        .as_array()?
+        .iter()
+        .map(|monitor| MonitorInfo {
+            name: monitor["name"].as_str().unwrap_or("").to_string(),
+            description: monitor["description"].as_str().unwrap_or("").to_string(),
+            focused: monitor["focused"].as_bool().unwrap_or(false),
+        })
+        .collect();
+    Some(monitors)
+}
+
+// updates layout on the keyboards in one request, each with its own index
+// returns keyboards Hyprland refused to switch
+fn change_layout(hyprctl: &Hyprctl, keyboards: &[(String, u16)]) -> Vec<String> {
+    let commands: Vec<String> = keyboards
+        .iter()
+        .map(|(kb, index)| format!("switchxkblayout {kb} {index}"))
+        .collect();
+    let results = match hyprctl.batch(&commands) {
+        Ok(results) => results,
+        Err(e) => {
+            log::warn!("Failed to change layout: {}", e);
+            return Vec::new();
+        }
+    };
+    let mut rejected = Vec::new();
+    for ((kb, index), result) in keyboards.iter().zip(results) {
+        match result {
+            Ok(()) => {
+                log::debug!("Layout changed kb:{} index:{}", kb, index);
+            }
+            Err(HyprctlError::Rejected(_, reply)) => {
+                log::warn!("Failed to change layout on {}: {}", kb, reply);
+                rejected.push(kb.clone());
+            }
+            Err(e) => log::warn!("Failed to change layout on {}: {}", kb, e),
+        }
+    }
+    rejected
+}
@@ -0,0 +1,636 @@
+//! messages from Hyprland event socket (.socket2.sock)
+//! each line looks like "name>>data", fields of data are separated by commas
+//! <https://wiki.hyprland.org/IPC/>
+
+/// window addresses are stored with 0x prefix, as in "j/clients" reply
+/// workspace ids are negative for special workspaces
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HyprEvent {
+    /// "workspace", the active workspace changed
+    Workspace {
+        /// workspace name
+        name: String,
+    },
+    /// "workspacev2", the active workspace changed
+    WorkspaceV2 {
+        /// workspace id
+        id: i64,
+        /// workspace name
+        name: String,
+    },
+    /// "focusedmon", another monitor got focus
+    FocusedMon {
+        /// monitor name, ex: eDP-1
+        monitor: String,
+        /// name of the active workspace on the monitor
+        workspace: String,
+    },
+    /// "focusedmonv2", another monitor got focus
+    FocusedMonV2 {
+        /// monitor name, ex: eDP-1
+        monitor: String,
+        /// id of the active workspace on the monitor
+        workspace_id: i64,
+    },
+    /// "activewindow", sent before ActiveWindowV2
+    ActiveWindow {
+        /// class of the focused window, empty if none
+        class: String,
+        /// title of the focused window, empty if none
+        title: String,
+    },
+    /// "activewindowv2", None if no window is focused (empty workspace)
+    ActiveWindowV2 {
+        /// address of the focused window
+        address: Option<String>,
+    },
+    /// "fullscreen", true when a window enters fullscreen
+    Fullscreen(bool),
+    /// "monitoradded"
+    MonitorAdded {
+        /// monitor name
+        name: String,
+    },
+    /// "monitoraddedv2"
+    MonitorAddedV2 {
+        /// monitor id
+        id: i64,
+        /// monitor name
+        name: String,
+        /// ex: Dell Inc. DELL U2720Q 1234567
+        description: String,
+    },
+    /// "monitorremoved"
+    MonitorRemoved {
+        /// monitor name
+        name: String,
+    },
+    /// "monitorremovedv2"
+    MonitorRemovedV2 {
+        /// monitor id
+        id: i64,
+        /// monitor name
+        name: String,
+        /// monitor description
+        description: String,
+    },
+    /// "createworkspace"
+    CreateWorkspace {
+        /// workspace name
+        name: String,
+    },
+    /// "createworkspacev2"
+    CreateWorkspaceV2 {
+        /// workspace id
+        id: i64,
+        /// workspace name
+        name: String,
+    },
+    /// "destroyworkspace"
+    DestroyWorkspace {
+        /// workspace name
+        name: String,
+    },
+    /// "destroyworkspacev2"
+    DestroyWorkspaceV2 {
+        /// workspace id
+        id: i64,
+        /// workspace name
+        name: String,
+    },
+    /// "moveworkspace", a workspace moved to another monitor
+    MoveWorkspace {
+        /// workspace name
+        name: String,
+        /// monitor name
+        monitor: String,
+    },
+    /// "moveworkspacev2", a workspace moved to another monitor
+    MoveWorkspaceV2 {
+        /// workspace id
+        id: i64,
+        /// workspace name
+        name: String,
+        /// monitor name
+        monitor: String,
+    },
+    /// "renameworkspace"
+    RenameWorkspace {
+        /// workspace id
+        id: i64,
+        /// new name
+        name: String,
+    },
+    /// "activespecial", name is empty when the special workspace is closed
+    ActiveSpecial {
+        /// workspace name, ex: special:term
+        name: String,
+        /// monitor name
+        monitor: String,
+    },
+    /// "activespecialv2", id is None when the special workspace is closed
+    ActiveSpecialV2 {
+        /// workspace id
+        id: Option<i64>,
+        /// workspace name, ex: special:term
+        name: String,
+        /// monitor name
+        monitor: String,
+    },
+    /// "activelayout", a keyboard switched its layout
+    ActiveLayout {
+        /// keyboard name
+        keyboard: String,
+        /// long name, ex: English (US)
+        layout: String,
+    },
+    /// "openwindow"
+    OpenWindow {
+        /// window address
+        address: String,
+        /// workspace name
+        workspace: String,
+        /// window class
+        class: String,
+        /// window title
+        title: String,
+    },
+    /// "closewindow"
+    CloseWindow {
+        /// window address
+        address: String,
+    },
+    /// "movewindow", a window moved to another workspace
+    MoveWindow {
+        /// window address
+        address: String,
+        /// workspace name
+        workspace: String,
+    },
+    /// "movewindowv2", a window moved to another workspace
+    MoveWindowV2 {
+        /// window address
+        address: String,
+        /// workspace id
+        workspace_id: i64,
+        /// workspace name
+        workspace: String,
+    },
+    /// "openlayer"
+    OpenLayer {
+        /// layer namespace, ex: rofi
+        namespace: String,
+    },
+    /// "closelayer"
+    CloseLayer {
+        /// layer namespace
+        namespace: String,
+    },
+    /// "submap", name is empty for the default submap
+    Submap {
+        /// submap name
+        name: String,
+    },
+    /// "changefloatingmode"
+    ChangeFloatingMode {
+        /// window address
+        address: String,
+        /// true if the window is floating now
+        floating: bool,
+    },
+    /// "urgent"
+    Urgent {
+        /// window address
+        address: String,
+    },
+    /// "minimized"
+    Minimized {
+        /// window address
+        address: String,
+        /// true if the window is minimized now
+        minimized: bool,
+    },
+    /// "pin"
+    Pin {
+        /// window address
+        address: String,
+        /// true if the window is pinned now
+        pinned: bool,
+    },
+    /// "windowtitle", title is not sent, request it with "j/clients"
+    WindowTitle {
+        /// window address
+        address: String,
+    },
+    /// "windowtitlev2"
+    WindowTitleV2 {
+        /// window address
+        address: String,
+        /// new title
+        title: String,
+    },
+    /// "configreloaded"
+    ConfigReloaded,
+    /// unknown or malformed event, kept as is
+    Other {
+        /// event name
+        name: String,
+        /// everything after ">>"
+        data: String,
+    },
+}
+
+impl HyprEvent {
+    /// parse a line from the event socket, ex: "activewindowv2>>5632c5a16e80"
+    /// returns None if the line has no ">>"
+    pub fn parse_line(line: &str) -> Option<HyprEvent> {
+        let line = line.trim_end_matches(['\n', '\r']);
+        let (name, data) = line.split_once(">>")?;
+        Some(HyprEvent::parse(name, data))
+    }
+
+    /// parse event name and its data
+    /// titles and workspace names may contain commas, so they are split off last
+    pub fn parse(name: &str, data: &str) -> HyprEvent {
+        match parse_known(name, data) {
+            Some(event) => event,
+            None => {
+                if KNOWN_EVENTS.contains(&name) {
+                    log::warn!("Bad '{}' format: {}", name, data);
+                }
+                HyprEvent::Other {
+                    name: name.to_string(),
+                    data: data.to_string(),
+                }
+            }
+        }
+    }
+}
+
+// events parse_known understands
+const KNOWN_EVENTS: &[&str] = &[
+    "workspace",
+    "workspacev2",
+    "focusedmon",
+    "focusedmonv2",
+    "activewindow",
+    "activewindowv2",
+    "fullscreen",
+    "monitoradded",
+    "monitoraddedv2",
+    "monitorremoved",
+    "monitorremovedv2",
+    "createworkspace",
+    "createworkspacev2",
+    "destroyworkspace",
+    "destroyworkspacev2",
+    "moveworkspace",
+    "moveworkspacev2",
+    "renameworkspace",
+    "activespecial",
+    "activespecialv2",
+    "activelayout",
+    "openwindow",
+    "closewindow",
+    "movewindow",
+    "movewindowv2",
+    "openlayer",
+    "closelayer",
+    "submap",
+    "changefloatingmode",
+    "urgent",
+    "minimized",
+    "pin",
+    "windowtitle",
+    "windowtitlev2",
+    "configreloaded",
+];
+
+// None if the event is unknown or its data is malformed
+fn parse_known(name: &str, data: &str) -> Option<HyprEvent> {
+    let s = |v: &str| v.to_string();
+    let event = match name {
+        "workspace" => HyprEvent::Workspace { name: s(data) },
+        "workspacev2" => {
+            let (id, name) = id_and_rest(data)?;
+            HyprEvent::WorkspaceV2 { id, name: s(name) }
+        }
+        // monitor names have no commas
+        "focusedmon" => {
+            let (monitor, workspace) = data.split_once(',')?;
+            HyprEvent::FocusedMon {
+                monitor: s(monitor),
+                workspace: s(workspace),
+            }
+        }
+        "focusedmonv2" => {
+            let (monitor, id) = data.split_once(',')?;
+            HyprEvent::FocusedMonV2 {
+                monitor: s(monitor),
+                workspace_id: id.parse().ok()?,
+            }
+        }
+        // classes have no commas
+        "activewindow" => {
+            let (class, title) = data.split_once(',').unwrap_or((data, ""));
+            HyprEvent::ActiveWindow {
+                class: s(class),
+                title: s(title),
+            }
+        }
+        "activewindowv2" => HyprEvent::ActiveWindowV2 {
+            address: match data {
+                "" | "," => None,
+                address => Some(with_prefix(address)),
+            },
+        },
+        "fullscreen" => HyprEvent::Fullscreen(flag(data)?),
+        "monitoradded" => HyprEvent::MonitorAdded { name: s(data) },
+        "monitoraddedv2" => {
+            let (id, name, description) = monitor_v2(data)?;
+            HyprEvent::MonitorAddedV2 {
+                id,
+                name,
+                description,
+            }
+        }
+        "monitorremoved" => HyprEvent::MonitorRemoved { name: s(data) },
+        "monitorremovedv2" => {
+            let (id, name, description) = monitor_v2(data)?;
+            HyprEvent::MonitorRemovedV2 {
+                id,
+                name,
+                description,
+            }
+        }
+        "createworkspace" => HyprEvent::CreateWorkspace { name: s(data) },
+        "createworkspacev2" => {
+            let (id, name) = id_and_rest(data)?;
+            HyprEvent::CreateWorkspaceV2 { id, name: s(name) }
+        }
+        "destroyworkspace" => HyprEvent::DestroyWorkspace { name: s(data) },
+        "destroyworkspacev2" => {
+            let (id, name) = id_and_rest(data)?;
+            HyprEvent::DestroyWorkspaceV2 { id, name: s(name) }
+        }
+        // workspace names may have commas, monitor is the last field
+        "moveworkspace" => {
+            let (name, monitor) = data.rsplit_once(',')?;
+            HyprEvent::MoveWorkspace {
+                name: s(name),
+                monitor: s(monitor),
+            }
+        }
+        "moveworkspacev2" => {
+            let (id, rest) = id_and_rest(data)?;
+            let (name, monitor) = rest.rsplit_once(',')?;
+            HyprEvent::MoveWorkspaceV2 {
+                id,
+                name: s(name),
+                monitor: s(monitor),
+            }
+        }
+        "renameworkspace" => {
+            let (id, name) = id_and_rest(data)?;
+            HyprEvent::RenameWorkspace { id, name: s(name) }
+        }
+        // params ex: special:term,DP-1 or ,DP-1 when closed
+        "activespecial" => {
+            let (name, monitor) = data.rsplit_once(',')?;
+            HyprEvent::ActiveSpecial {
+                name: s(name),
+                monitor: s(monitor),
+            }
+        }
+        // params ex: -98,special:term,DP-1 or ,,DP-1 when closed
+        "activespecialv2" => {
+            let (id, rest) = data.split_once(',')?;
+            let (name, monitor) = rest.rsplit_once(',')?;
+            let id = match id {
+                "" => None,
+                id => Some(id.parse().ok()?),
+            };
+            HyprEvent::ActiveSpecialV2 {
+                id,
+                name: s(name),
+                monitor: s(monitor),
+            }
+        }
+        // keyboard names have no commas, layout names may have
+        // params ex: at-translated-set-2-keyboard,English (US, intl., with dead keys)
+        "activelayout" => {
+            let (keyboard, layout) = data.split_once(',')?;
+            HyprEvent::ActiveLayout {
+                keyboard: s(keyboard),
+                layout: s(layout),
+            }
+        }
+        // params ex: 5632c5a16e80,2,kitty,~
+        "openwindow" => {
+            let params: Vec<&str> = data.splitn(4, ',').collect();
+            if params.len() != 4 {
+                return None;
+            }
+            HyprEvent::OpenWindow {
+                address: with_prefix(params[0]),
+                workspace: s(params[1]),
+                class: s(params[2]),
+                title: s(params[3]),
+            }
+        }
+        "closewindow" => HyprEvent::CloseWindow {
+            address: with_prefix(data),
+        },
+        "movewindow" => {
+            let (address, workspace) = data.split_once(',')?;
+            HyprEvent::MoveWindow {
+                address: with_prefix(address),
+                workspace: s(workspace),
+            }
+        }
+        "movewindowv2" => {
+            let (address, rest) = data.split_once(',')?;
+            let (workspace_id, workspace) = id_and_rest(rest)?;
+            HyprEvent::MoveWindowV2 {
+                address: with_prefix(address),
+                workspace_id,
+                workspace: s(workspace),
+            }
+        }
+        "openlayer" => HyprEvent::OpenLayer { namespace: s(data) },
+        "closelayer" => HyprEvent::CloseLayer { namespace: s(data) },
+        "submap" => HyprEvent::Submap { name: s(data) },
+        "changefloatingmode" => {
+            let (address, floating) = data.split_once(',')?;
+            HyprEvent::ChangeFloatingMode {
+                address: with_prefix(address),
+                floating: flag(floating)?,
+            }
+        }
+        "urgent" => HyprEvent::Urgent {
+            address: with_prefix(data),
+        },
+        "minimized" => {
+            let (address, minimized) = data.split_once(',')?;
+            HyprEvent::Minimized {
+                address: with_prefix(address),
+                minimized: flag(minimized)?,
+            }
+        }
+        "pin" => {
+            let (address, pinned) = data.split_once(',')?;
+            HyprEvent::Pin {
+                address: with_prefix(address),
+                pinned: flag(pinned)?,
+            }
+        }
+        "windowtitle" => HyprEvent::WindowTitle {
+            address: with_prefix(data),
+        },
+        "windowtitlev2" => {
+            let (address, title) = data.split_once(',')?;
+            HyprEvent::WindowTitleV2 {
+                address: with_prefix(address),
+                title: s(title),
+            }
+        }
+        "configreloaded" => HyprEvent::ConfigReloaded,
+        _ => return None,
+    };
+    Some(event)
+}
+
+fn with_prefix(address: &str) -> String {
+    if address.starts_with("0x") {
+        address.to_string()
+    } else {
+        format!("0x{address}")
+    }
+}
+
+fn flag(value: &str) -> Option<bool> {
+    match value {
+        "0" => Some(false),
+        "1" => Some(true),
+        _ => None,
+    }
+}
+
+// split "2,name:chat" into 2 and "name:chat"
+fn id_and_rest(data: &str) -> Option<(i64, &str)> {
+    let (id, rest) = data.split_once(',')?;
+    Some((id.parse().ok()?, rest))
+}
+
+// params ex: 1,DP-1,Dell Inc. DELL U2720Q
+fn monitor_v2(data: &str) -> Option<(i64, String, String)> {
+    let (id, rest) = id_and_rest(data)?;
+    let (name, description) = rest.split_once(',').unwrap_or((rest, ""));
+    Some((id, name.to_string(), description.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> HyprEvent {
+        HyprEvent::parse_line(line).unwrap()
+    }
+
+    #[test]
+    fn titles_keep_commas() {
+        assert_eq!(
+            parse("openwindow>>5632c5a16e80,2,kitty,vim a, b"),
+            HyprEvent::OpenWindow {
+                address: "0x5632c5a16e80".to_string(),
+                workspace: "2".to_string(),
+                class: "kitty".to_string(),
+                title: "vim a, b".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("activewindow>>firefox,Inbox, 3 unread >> Gmail"),
+            HyprEvent::ActiveWindow {
+                class: "firefox".to_string(),
+                title: "Inbox, 3 unread >> Gmail".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("windowtitlev2>>5632c5a16e80,a,b"),
+            HyprEvent::WindowTitleV2 {
+                address: "0x5632c5a16e80".to_string(),
+                title: "a,b".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn layout_names_keep_commas() {
+        assert_eq!(
+            parse("activelayout>>at-keyboard,English (US, intl., with dead keys)"),
+            HyprEvent::ActiveLayout {
+                keyboard: "at-keyboard".to_string(),
+                layout: "English (US, intl., with dead keys)".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn workspaces_and_monitors() {
+        assert_eq!(
+            parse("workspacev2>>3,name:a,b"),
+            HyprEvent::WorkspaceV2 {
+                id: 3,
+                name: "name:a,b".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("focusedmonv2>>DP-1,-98"),
+            HyprEvent::FocusedMonV2 {
+                monitor: "DP-1".to_string(),
+                workspace_id: -98,
+            }
+        );
+        assert_eq!(
+            parse("movewindowv2>>5632c5a16e80,4,code"),
+            HyprEvent::MoveWindowV2 {
+                address: "0x5632c5a16e80".to_string(),
+                workspace_id: 4,
+                workspace: "code".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("activespecialv2>>,,DP-1"),
+            HyprEvent::ActiveSpecialV2 {
+                id: None,
+                name: String::new(),
+                monitor: "DP-1".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn empty_and_unknown_events() {
+        assert_eq!(
+            parse("activewindowv2>>"),
+            HyprEvent::ActiveWindowV2 { address: None }
+        );
+        assert_eq!(parse("configreloaded>>"), HyprEvent::ConfigReloaded);
+        assert_eq!(
+            parse("fullscreen>>2"),
+            HyprEvent::Other {
+                name: "fullscreen".to_string(),
+                data: "2".to_string(),
+            }
+        );
+        assert_eq!(
+            parse("someday>>a,b"),
+            HyprEvent::Other {
+                name: "someday".to_string(),
+                data: "a,b".to_string(),
+            }
+        );
+        assert_eq!(HyprEvent::parse_line("garbage"), None);
+    }
+}
@@ -0,0 +1,218 @@
+//! talk to Hyprland through its request socket (.socket.sock), the same way hyprctl does
+//! requests look like "j/devices" (json output) or "switchxkblayout kb 1"
+
+use serde_json::Value;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+/// failed request to Hyprland
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum HyprctlError {
+    /// HYPRLAND_INSTANCE_SIGNATURE is not set or no instance listens
+    NoInstance,
+    /// failed to talk to the socket
+    Io(std::io::Error),
+    /// reply is not a valid json, with the parser message
+    Json(String),
+    /// request and the error returned by Hyprland
+    Rejected(String, String),
+}
+
+impl fmt::Display for HyprctlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HyprctlError::NoInstance => write!(f, "Hyprland is not running"),
+            HyprctlError::Io(e) => write!(f, "Hyprland socket error: {e}"),
+            HyprctlError::Json(e) => write!(f, "Failed to parse JSON: {e}"),
+            HyprctlError::Rejected(request, reply) => {
+                write!(f, "Hyprland rejected '{request}': {reply}")
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for HyprctlError {
+    fn from(e: std::io::Error) -> Self {
+        HyprctlError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for HyprctlError {
+    fn from(e: serde_json::Error) -> Self {
+        HyprctlError::Json(e.to_string())
+    }
+}
+
+/// client of one Hyprland instance, talks to the sockets in its directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hyprctl {
+    dir: PathBuf,
+}
+
+impl Hyprctl {
+    /// client of the instance with sockets in this directory
+    pub fn new(dir: PathBuf) -> Hyprctl {
+        Hyprctl { dir }
+    }
+
+    /// instance from HYPRLAND_INSTANCE_SIGNATURE
+    /// sockets are in $XDG_RUNTIME_DIR/hypr/$HYPRLAND_INSTANCE_SIGNATURE or /tmp/hypr/... for older versions
+    pub fn from_env() -> Result<Hyprctl, HyprctlError> {
+        let hypr_inst =
+            env::var("HYPRLAND_INSTANCE_SIGNATURE").map_err(|_| HyprctlError::NoInstance)?;
+        let default_dir = PathBuf::from(format!("/tmp/hypr/{hypr_inst}")); // for backawards compatibility
+        let dir = match env::var("XDG_RUNTIME_DIR") {
+            Ok(runtime_dir) => {
+                let dir = PathBuf::from(format!("{runtime_dir}/hypr/{hypr_inst}"));
+                if dir.join(".socket2.sock").exists() {
+                    dir
+                } else {
+                    default_dir
+                }
+            }
+            Err(..) => default_dir,
+        };
+        Ok(Hyprctl::new(dir))
+    }
+
+    /// find the running Hyprland instance
+    /// if the one from HYPRLAND_INSTANCE_SIGNATURE doesn't listen (Hyprland was restarted),
+    /// take the newest live instance in $XDG_RUNTIME_DIR/hypr
+    pub fn resolve() -> Result<Hyprctl, HyprctlError> {
+        let hyprctl = Hyprctl::from_env()?;
+        if is_alive(&hyprctl.dir) {
+            return Ok(hyprctl);
+        }
+        let runtime_dir = env::var("XDG_RUNTIME_DIR").map_err(|_| HyprctlError::NoInstance)?;
+        let newest = fs::read_dir(format!("{runtime_dir}/hypr"))?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| is_alive(&entry.path()))
+            .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok());
+        match newest {
+            Some(entry) => {
+                log::debug!("Found Hyprland instance {:?}", entry.file_name());
+                Ok(Hyprctl::new(entry.path()))
+            }
+            None => Err(HyprctlError::NoInstance),
+        }
+    }
+
+    /// directory with Hyprland sockets
+    pub fn socket_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// path of the event socket (.socket2.sock)
+    pub fn event_socket(&self) -> PathBuf {
+        self.dir.join(".socket2.sock")
+    }
+
+    /// send a request and read the whole reply
+    pub fn request(&self, command: &str) -> Result<String, HyprctlError> {
+        let mut stream = UnixStream::connect(self.dir.join(".socket.sock"))?;
+        stream.write_all(command.as_bytes())?;
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply)?;
+        log::debug!("Request '{}' reply: {}", command, reply.trim());
+        Ok(reply)
+    }
+
+    /// send a request with json output, ex: request_json("devices")
+    pub fn request_json(&self, command: &str) -> Result<Value, HyprctlError> {
+        let reply = self.request(&format!("j/{command}"))?;
+        Ok(serde_json::from_str(&reply)?)
+    }
+
+    /// send many commands in one round trip
+    /// returns result of each command in the same order
+    pub fn batch(
+        &self,
+        commands: &[String],
+    ) -> Result<Vec<Result<(), HyprctlError>>, HyprctlError> {
+        if commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        let reply = self.request(&format!("[[BATCH]]{}", commands.join(";")))?;
+        Ok(batch_results(commands, &reply))
+    }
+
+    /// get value of a string option from hyprland conf file
+    pub fn get_option_str(&self, option: &str) -> Option<String> {
+        let json = self.request_json(&format!("getoption {option}")).ok()?;
+        json["str"]
+            .as_str()
+            .map(|s| s.trim().to_string())
+            .filter(|s| s != "[[EMPTY]]")
+    }
+}
+
+// the socket file stays after Hyprland crashed, only a listening socket is alive
+fn is_alive(dir: &Path) -> bool {
+    UnixStream::connect(dir.join(".socket2.sock")).is_ok()
+}
+
+// replies are separated by empty lines, a missing reply is an error
+fn batch_results(commands: &[String], reply: &str) -> Vec<Result<(), HyprctlError>> {
+    let replies: Vec<&str> = reply.split("\n\n").collect();
+    commands
+        .iter()
+        .enumerate()
+        .map(|(i, command)| check_reply(command, replies.get(i).copied().unwrap_or("")))
+        .collect()
+}
+
+fn check_reply(command: &str, reply: &str) -> Result<(), HyprctlError> {
+    if reply.trim() == "ok" {
+        Ok(())
+    } else {
+        Err(HyprctlError::Rejected(
+            command.to_string(),
+            reply.trim().to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results(commands: &[&str], reply: &str) -> Vec<Result<(), String>> {
+        let commands: Vec<String> = commands.iter().map(|c| c.to_string()).collect();
+        batch_results(&commands, reply)
+            .into_iter()
+            .map(|result| result.map_err(|e| e.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn batch_reply_is_split_by_commands() {
+        let commands = ["switchxkblayout kb1 1", "switchxkblayout kb2 1"];
+        assert_eq!(results(&commands, "ok\n\nok"), [Ok(()), Ok(())]);
+        assert_eq!(
+            results(&commands, "ok\n\ndevice not found\n"),
+            [
+                Ok(()),
+                Err("Hyprland rejected 'switchxkblayout kb2 1': device not found".to_string())
+            ]
+        );
+        assert_eq!(
+            results(&commands, "ok"),
+            [
+                Ok(()),
+                Err("Hyprland rejected 'switchxkblayout kb2 1': ".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn only_ok_is_success() {
+        assert!(check_reply("dispatch", " ok\n").is_ok());
+        assert!(check_reply("dispatch", "okay").is_err());
+        assert!(check_reply("dispatch", "").is_err());
+    }
+}
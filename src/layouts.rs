@@ -0,0 +1,269 @@
+//! refer to layouts by name in options.toml
+//! names are resolved to indices of input:kb_layout at startup
+
+use std::collections::HashMap;
+use std::fs;
+
+use crate::hyprctl::Hyprctl;
+
+/// layout as written in options.toml
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum LayoutRef {
+    /// 1
+    Index(u16),
+    /// "us", "de(nodeadkeys)" or "English (US)"
+    Name(String),
+}
+
+impl LayoutRef {
+    /// parse a key of `[[default_layouts]]` table
+    pub fn from_key(key: &str) -> LayoutRef {
+        match key.parse::<u16>() {
+            Ok(index) => LayoutRef::Index(index),
+            Err(_) => LayoutRef::Name(key.to_string()),
+        }
+    }
+
+    /// index of the layout, None for names that weren't resolved
+    pub fn index(&self) -> Option<u16> {
+        match self {
+            LayoutRef::Index(index) => Some(*index),
+            LayoutRef::Name(_) => None,
+        }
+    }
+
+    /// parse a value like layout = 1 or layout = "us"
+    pub(crate) fn from_value(value: &toml::Value) -> Result<LayoutRef, String> {
+        match value {
+            toml::Value::Integer(index) => u16::try_from(*index)
+                .map(LayoutRef::Index)
+                .map_err(|_| format!("bad layout index {index}")),
+            toml::Value::String(name) => Ok(LayoutRef::from_key(name)),
+            _ => Err("layout should be a number or a name".to_string()),
+        }
+    }
+}
+
+/// short names with variants, ex: "us,de" and ",nodeadkeys" => us, de(nodeadkeys)
+pub fn layout_names(kb_layout: &str, kb_variant: &str) -> Vec<String> {
+    let variants: Vec<&str> = kb_variant.split(',').map(|v| v.trim()).collect();
+    let mut names = Vec::new();
+    for (i, layout) in kb_layout.split(',').map(|l| l.trim()).enumerate() {
+        if layout.is_empty() {
+            continue;
+        }
+        let variant = variants.get(i).copied().unwrap_or("");
+        if variant.is_empty() {
+            names.push(layout.to_string());
+        } else {
+            names.push(format!("{layout}({variant})"));
+        }
+    }
+    names
+}
+
+/// a keyboard and its own layouts, from "j/devices" request
+/// they differ from input:kb_layout with a `device { kb_layout = ... }` section
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct KeyboardInfo {
+    /// device name, ex: at-translated-set-2-keyboard
+    pub name: String,
+    /// short names in the order of the keyboard layouts, empty if unknown
+    pub layouts: Vec<String>,
+}
+
+impl KeyboardInfo {
+    /// keyboard with layouts from its kb_layout and kb_variant, ex: "us,de" and ",nodeadkeys"
+    pub fn new(name: &str, kb_layout: &str, kb_variant: &str) -> KeyboardInfo {
+        KeyboardInfo {
+            name: name.to_string(),
+            layouts: layout_names(kb_layout, kb_variant),
+        }
+    }
+}
+
+/// layouts configured in Hyprland, in the order of input:kb_layout
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct KbLayouts {
+    /// short names: "us", "de(nodeadkeys)"
+    pub names: Vec<String>,
+    /// names used by xkb: "English (US)"
+    pub long_names: Vec<String>,
+}
+
+impl KbLayouts {
+    /// build from the values of input:kb_layout and input:kb_variant options
+    /// ex: "us,de" and ",nodeadkeys"
+    /// long names are taken from descriptions, see [`xkb_descriptions`]
+    pub fn new(
+        kb_layout: &str,
+        kb_variant: &str,
+        descriptions: &HashMap<String, String>,
+    ) -> KbLayouts {
+        let mut kb = KbLayouts::default();
+        for name in layout_names(kb_layout, kb_variant) {
+            let long_name = descriptions.get(&name).cloned().unwrap_or_default();
+            kb.names.push(name);
+            kb.long_names.push(long_name);
+        }
+        kb
+    }
+
+    /// get layouts listed in hyprland conf file (input section) with their variants
+    pub fn fetch(hyprctl: &Hyprctl) -> KbLayouts {
+        let kb_layout = hyprctl
+            .get_option_str("input:kb_layout")
+            .unwrap_or_default();
+        let kb_variant = hyprctl
+            .get_option_str("input:kb_variant")
+            .unwrap_or_default();
+        let kb_layouts = KbLayouts::new(&kb_layout, &kb_variant, &xkb_descriptions());
+        log::debug!("Layouts: {:?}", kb_layouts);
+        kb_layouts
+    }
+
+    /// find index of a layout by short or long name
+    /// without input:kb_layout (kb_file is used) layouts seen in 'activelayout' events are checked
+    pub fn resolve(&self, layout: &LayoutRef, seen: &[String]) -> Result<u16, String> {
+        let name = match layout {
+            LayoutRef::Index(index) => return Ok(*index),
+            LayoutRef::Name(name) => name,
+        };
+        let position = self
+            .names
+            .iter()
+            .position(|n| n == name)
+            .or_else(|| self.long_names.iter().position(|n| n == name));
+        if let Some(index) = position {
+            return Ok(index as u16);
+        }
+        // seen layouts are in the order they were switched to, not in the order of the keymap
+        let seen = if self.names.is_empty() { seen } else { &[] };
+        if let Some(index) = seen.iter().position(|n| n == name) {
+            log::warn!(
+                "Layout '{}' is resolved to {} by the order it was seen, use an index if it is wrong",
+                name,
+                index
+            );
+            return Ok(index as u16);
+        }
+        let mut available: Vec<&str> = self.names.iter().map(|n| n.as_str()).collect();
+        for long_name in self.long_names.iter().chain(seen.iter()) {
+            if !long_name.is_empty() && !available.contains(&long_name.as_str()) {
+                available.push(long_name);
+            }
+        }
+        Err(format!(
+            "unknown layout '{}', available: {}",
+            name,
+            available.join(", ")
+        ))
+    }
+}
+
+/// read layout descriptions from the xkb registry, $XKB_CONFIG_ROOT/rules/evdev.lst
+/// "us" => "English (US)", "de(nodeadkeys)" => "German (no dead keys)"
+pub fn xkb_descriptions() -> HashMap<String, String> {
+    let root = std::env::var("XKB_CONFIG_ROOT").unwrap_or("/usr/share/X11/xkb".to_string());
+    match fs::read_to_string(format!("{root}/rules/evdev.lst")) {
+        Ok(content) => parse_xkb_descriptions(&content),
+        Err(e) => {
+            log::debug!("Failed to read xkb rules: {}", e);
+            HashMap::new()
+        }
+    }
+}
+
+fn parse_xkb_descriptions(content: &str) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    // file consists of sections like:
+    // ! layout
+    //   us              English (US)
+    // ! variant
+    //   nodeadkeys      de: German (no dead keys)
+    let mut section = "";
+    for line in content.lines() {
+        if let Some(name) = line.strip_prefix("! ") {
+            section = name.trim();
+            continue;
+        }
+        let line = line.trim();
+        let (name, description) = match line.split_once(char::is_whitespace) {
+            Some((name, description)) => (name, description.trim()),
+            None => continue,
+        };
+        match section {
+            "layout" => {
+                map.insert(name.to_string(), description.to_string());
+            }
+            "variant" => {
+                if let Some((layout, description)) = description.split_once(": ") {
+                    map.insert(format!("{layout}({name})"), description.to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variants_follow_layouts() {
+        assert_eq!(
+            layout_names("us, de,ua", ",nodeadkeys"),
+            ["us", "de(nodeadkeys)", "ua"]
+        );
+        assert_eq!(layout_names("us,,de", ""), ["us", "de"]);
+        assert!(layout_names("", "").is_empty());
+    }
+
+    #[test]
+    fn descriptions_are_read_from_sections() {
+        let content = "! model\n  pc105           Generic 105-key PC\n\n\
+                       ! layout\n  us              English (US)\n  de              German\n\n\
+                       ! variant\n  nodeadkeys      de: German (no dead keys)\n  broken\n\n\
+                       ! option\n  grp             Switching to another layout\n";
+        let descriptions = parse_xkb_descriptions(content);
+        assert_eq!(descriptions.len(), 3);
+        assert_eq!(descriptions["us"], "English (US)");
+        assert_eq!(descriptions["de"], "German");
+        assert_eq!(descriptions["de(nodeadkeys)"], "German (no dead keys)");
+    }
+
+    #[test]
+    fn short_names_are_resolved_first() {
+        let kb = KbLayouts {
+            names: vec!["us".to_string(), "de".to_string()],
+            long_names: vec!["English (US)".to_string(), "us".to_string()],
+        };
+        let seen = ["German".to_string(), "English (US)".to_string()];
+        let resolve = |name: &str| kb.resolve(&LayoutRef::from_key(name), &seen);
+        assert_eq!(resolve("us"), Ok(0));
+        assert_eq!(resolve("English (US)"), Ok(0));
+        assert_eq!(resolve("3"), Ok(3));
+        // the order of seen layouts is not the order of kb_layout
+        assert_eq!(
+            resolve("German"),
+            Err("unknown layout 'German', available: us, de, English (US)".to_string())
+        );
+    }
+
+    #[test]
+    fn seen_layouts_are_used_with_kb_file() {
+        let kb = KbLayouts::default();
+        let seen = ["German".to_string(), "English (US)".to_string()];
+        let resolve = |name: &str| kb.resolve(&LayoutRef::from_key(name), &seen);
+        assert_eq!(resolve("English (US)"), Ok(1));
+        assert_eq!(
+            resolve("us"),
+            Err("unknown layout 'us', available: German, English (US)".to_string())
+        );
+    }
+}
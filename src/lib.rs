@@ -0,0 +1,60 @@
+//! per window keyboard layout for Hyprland
+//! the daemon `hyprland-per-window-layout` is built on top of this library,
+//! use it to parse Hyprland events, talk to its request socket,
+//! read options.toml or drive the layout state machine in your own tools
+//!
+//! - [`events`]: typed messages from the event socket (`.socket2.sock`)
+//! - [`hyprctl`]: requests to the request socket (`.socket.sock`)
+//! - [`options`], [`rules`], [`layouts`], [`validate`]: options.toml model
+//! - [`state`]: layout state machine, events in and actions out, no I/O
+//! - [`daemon`]: performs the actions of the state machine against Hyprland
+//! - [`state_file`]: layouts learned between restarts
+//! - [`process`]: processes of windows
+//!
+//! ```
+//! use hyprland_per_window_layout::events::HyprEvent;
+//! use hyprland_per_window_layout::layouts::KbLayouts;
+//! use hyprland_per_window_layout::options::Options;
+//! use hyprland_per_window_layout::state::{Action, State};
+//! use std::collections::HashMap;
+//!
+//! let mut options = Options::default();
+//! options.keyboards.push("kb1".to_string());
+//! let mut state = State::new();
+//! let descriptions = HashMap::from([
+//!     ("us".to_string(), "English (US)".to_string()),
+//!     ("de".to_string(), "German".to_string()),
+//! ]);
+//! state.set_options(options, KbLayouts::new("us,de", "", &descriptions));
+//! state.set_active_keymap("English (US)");
+//!
+//! // user opened a terminal and switched it to German
+//! for line in [
+//!     "openwindow>>a1,1,kitty,~",
+//!     "activewindow>>kitty,~",
+//!     "activewindowv2>>a1",
+//!     "activelayout>>kb1,German",
+//!     "openwindow>>b2,1,firefox,Mozilla Firefox",
+//!     "activewindow>>firefox,Mozilla Firefox",
+//! ] {
+//!     let event = HyprEvent::parse_line(line).unwrap();
+//!     assert!(state.handle(event.into()).is_empty());
+//! }
+//! // a new window gets the first layout
+//! let event = HyprEvent::parse_line("activewindowv2>>b2").unwrap();
+//! assert_eq!(
+//!     state.handle(event.into()),
+//!     [Action::SwitchLayout { keyboards: vec![("kb1".to_string(), 0)], index: 0 }]
+//! );
+//! ```
+
+pub mod daemon;
+pub mod events;
+pub mod hyprctl;
+pub mod layouts;
+pub mod options;
+pub mod process;
+pub mod rules;
+pub mod state;
+pub mod state_file;
+pub mod validate;
@@ -1,72 +1,89 @@
-use std::env; // read env variables
-use std::io::BufRead; // read unix socket
-use std::io::BufReader; // read unix socket
-use std::os::unix::net::UnixStream;
+// the daemon and its client commands
+// the logic lives in the library crate, see lib.rs
 
-mod hyprland_event; // work with message from socket
-use hyprland_event::{event, fullfill_keyboards_list, fullfill_layouts_list, hyprctl};
+use hyprland_per_window_layout::daemon::Daemon;
+use hyprland_per_window_layout::hyprctl::Hyprctl;
+use hyprland_per_window_layout::options::options_path;
 
-mod options; // read options.toml
-use options::read_options;
+mod cli; // client subcommands
+mod control; // requests from control socket
 
 mod single; // a struct representing one running instance
 use single::SingleInstance;
 
-use serde_json::Value; // json parsed
+mod watch; // reload options.toml on change
+use watch::watch_options;
 
-// listen Hyprland socket
-fn listen(socket_addr: String) -> std::io::Result<()> {
-    let stream = match UnixStream::connect(socket_addr) {
-        Ok(stream) => stream,
-        Err(e) => {
-            println!("Couldn't connect: {e:?}");
-            return Err(e);
-        }
-    };
-    let mut reader = BufReader::new(stream);
-    let opt = read_options();
-    if !opt.keyboards.is_empty() {
-        for keyboard in opt.keyboards.iter() {
-            fullfill_keyboards_list(keyboard.to_string());
-            log::debug!("Keyboard added: {}", keyboard);
-        }
+use nix::sys::signal::{SigSet, Signal};
+use std::time::{Duration, Instant};
+
+// name of the socket for single instance check and control requests
+const INSTANCE_NAME: &str = "hyprland-per-window-layout";
+// overrides the socket name, ex: for tests that shouldn't meet the running daemon
+const INSTANCE_ENV: &str = "HYPRLAND_PER_WINDOW_LAYOUT_INSTANCE";
+
+// reconnect delay grows from MIN to MAX seconds
+const MIN_RECONNECT_DELAY: u64 = 1;
+const MAX_RECONNECT_DELAY: u64 = 30;
+// give up after this many failed reconnects in a row
+const MAX_RECONNECTS: u32 = 20;
+// connection that lasted longer than this is considered healthy
+const HEALTHY_CONNECTION: Duration = Duration::from_secs(10);
+// Hyprland has no events for plugged keyboards, so we ask it
+const KEYBOARDS_REFRESH: Duration = Duration::from_secs(5);
+
+fn instance_name() -> String {
+    std::env::var(INSTANCE_ENV)
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| INSTANCE_NAME.to_string())
+}
+
+// save learned layouts before exit on SIGTERM or SIGINT
+// reload options on SIGHUP
+fn handle_signals(daemon: Daemon) {
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGTERM);
+    mask.add(Signal::SIGINT);
+    mask.add(Signal::SIGHUP);
+    if let Err(e) = mask.thread_block() {
+        log::warn!("Failed to block signals: {}", e);
+        return;
     }
-    loop {
-        // read message from socket
-        let mut buf: Vec<u8> = vec![];
-        let readed = match reader.read_until(b'\n', &mut buf) {
-            Ok(size) => size,
-            Err(e) => {
-                log::warn!("Error reading from socket: {}", e);
-                break Err(e);
+    std::thread::spawn(move || loop {
+        match mask.wait() {
+            Ok(Signal::SIGHUP) => {
+                log::debug!("Received SIGHUP, reloading options");
+                if let Err(e) = daemon.reload_options() {
+                    println!("{e}");
+                    println!("Keep using previous options");
+                }
             }
-        };
-        if readed == 0 {
-            break Ok(());
-        }
-        let data = String::from_utf8_lossy(&buf);
-        let data_parts: Vec<&str> = data.trim().split(">>").collect();
-        if data_parts.len() > 1 {
-            event(data_parts[0], data_parts[1], &opt)
+            Ok(signal) => {
+                log::debug!("Received {}, saving state", signal);
+                daemon.save_learned();
+                std::process::exit(0);
+            }
+            Err(_) => std::process::exit(0),
         }
-    }
+    });
+}
+
+// add plugged keyboards and remove unplugged ones
+fn refresh_keyboards_periodically(daemon: Daemon) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(KEYBOARDS_REFRESH);
+        daemon.refresh_keyboards();
+    });
 }
 
 // get keyboards count listed in hyprland conf file (input section)
 // return -1 if failed
-fn get_kb_layouts_count() -> i16 {
-    // get layouts list from hyprctl cli call
-    match hyprctl(["getoption", "input:kb_layout", "-j"].to_vec()) {
-        Ok(output) => {
-            log::debug!("input:kb_layout: {}", output);
-            // parse the string from stdin into serde_json::Value
-            let json: Value = match serde_json::from_str(&output) {
-                Ok(json) => json,
-                Err(e) => {
-                    log::warn!("Failed to parse JSON: {}", e);
-                    return -1;
-                }
-            };
+fn get_kb_layouts_count(hyprctl: &Hyprctl) -> i16 {
+    // get layouts list from Hyprland socket
+    match hyprctl.request_json("getoption input:kb_layout") {
+        Ok(json) => {
+            log::debug!("input:kb_layout: {}", json);
             if json.is_null() || json["str"].is_null() {
                 return -1;
             }
@@ -79,18 +96,18 @@ fn get_kb_layouts_count() -> i16 {
                 0
             }
         }
-        Err(_e) => {
-            println!("Failed to get option from hyprctl");
-            0
+        Err(e) => {
+            println!("Failed to get option input:kb_layout: {e}");
+            -1
         }
     }
 }
 
 // try to get kb layouts count 5 times with 1 sec delay
-fn get_kb_layouts_count_retry() -> i16 {
+fn get_kb_layouts_count_retry(hyprctl: &Hyprctl) -> i16 {
     let mut count = 0;
     loop {
-        let layouts_found = get_kb_layouts_count();
+        let layouts_found = get_kb_layouts_count(hyprctl);
         if layouts_found > -1 {
             return layouts_found;
         }
@@ -103,45 +120,17 @@ fn get_kb_layouts_count_retry() -> i16 {
 }
 
 // check kb_file option is set in hyprland conf file
-fn kb_file_isset() -> bool {
-    // get layouts list from hyprctl cli call
-    match hyprctl(["getoption", "input:kb_file", "-j"].to_vec()) {
-        Ok(output) => {
-            log::debug!("input:kb_file: {}", output);
-            // parse the string from stdin into serde_json::Value
-            let json: Value = match serde_json::from_str(&output) {
-                Ok(json) => json,
-                Err(e) => {
-                    log::warn!("Failed to parse JSON: {}", e);
-                    return false;
-                }
-            };
-            if json["str"].is_null() {
-                return false;
-            }
-            let value = str::replace(json["str"].to_string().trim(), "\"", "");
-            value != "[[EMPTY]]"
-        }
-        Err(_e) => {
-            println!("Failed to get option from hyprctl");
-            false
-        }
-    }
+fn kb_file_isset(hyprctl: &Hyprctl) -> bool {
+    hyprctl
+        .get_option_str("input:kb_file")
+        .is_some_and(|value| !value.is_empty())
 }
 
-// get default layout from cli command "hyprctl devices -j"
+// get default layout from "j/devices" request
 // value of ['keyboards'][0]['active_keymap']
-fn get_default_layout_name() -> bool {
-    match hyprctl(["devices", "-j"].to_vec()) {
-        Ok(output) => {
-            // parse the string from stdin into serde_json::Value
-            let json: Value = match serde_json::from_str(&output) {
-                Ok(json) => json,
-                Err(e) => {
-                    log::warn!("Failed to parse JSON: {}", e);
-                    return false;
-                }
-            };
+fn get_default_layout_name(daemon: &Daemon, hyprctl: &Hyprctl) -> bool {
+    match hyprctl.request_json("devices") {
+        Ok(json) => {
             let keyboards = &json["keyboards"];
             log::debug!("keyboards: {}", keyboards);
             if keyboards.is_null() {
@@ -165,33 +154,36 @@ fn get_default_layout_name() -> bool {
                 "",
             );
             if !kb_layout.is_empty() {
-                fullfill_layouts_list(kb_layout.to_string());
+                daemon.set_active_keymap(&kb_layout);
                 true
             } else {
                 log::warn!("Keyboard layouts not found");
                 false
             }
         }
-        Err(_e) => {
-            println!("Failed to get devices from hyprctl");
+        Err(e) => {
+            println!("Failed to get devices: {e}");
             false
         }
     }
 }
 
-// read env variables and listen Hyprland unix socket
-fn main() {
-    // to see logs in output: add env RUST_LOG='debug'
-    env_logger::init();
-    let instance_sock = SingleInstance::new("hyprland-per-window-layout").unwrap();
-    if !instance_sock.is_single() {
-        println!("Another instance is running.");
-        std::process::exit(1);
-    }
+// check layouts configured in Hyprland and find the current one
+// exits if the configuration is not usable at start
+// returns false if Hyprland is not ready or not usable after reconnect
+fn discover_layouts(daemon: &Daemon, hyprctl: &Hyprctl, reconnect: bool) -> bool {
     // this program make sense if you have 2+ layouts
-    let layouts_found = get_kb_layouts_count_retry();
+    let layouts_found = get_kb_layouts_count_retry(hyprctl);
+    if layouts_found < 0 && reconnect {
+        return false;
+    }
 
-    if layouts_found < 2 && !kb_file_isset() {
+    if layouts_found < 2 && !kb_file_isset(hyprctl) {
+        // the config may be fixed while we wait to reconnect
+        if reconnect {
+            log::warn!("Less than 2 layouts are configured in Hyprland");
+            return false;
+        }
         println!("Fatal error: You need to configure layouts on Hyprland");
         println!("Add kb_layout option to input group in your hyprland.conf");
         println!("You don't need this program if you have only 1 keyboard layout");
@@ -199,36 +191,104 @@ fn main() {
     }
     let mut attempts = 0;
     const MAX_ATTEMPTS: u32 = 30; // 30 second timeout
-    while !get_default_layout_name() {
+    while !get_default_layout_name(daemon, hyprctl) {
         // repeat until success
         attempts += 1;
         if attempts >= MAX_ATTEMPTS {
             println!("Timeout: Could not get default layout after {MAX_ATTEMPTS} seconds");
+            if reconnect {
+                return false;
+            }
             std::process::exit(1);
         }
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    true
+}
+
+// seconds to wait before the reconnect after this many failures in a row, doubled each time
+fn reconnect_delay(failures: u32) -> u64 {
+    (MIN_RECONNECT_DELAY << failures.saturating_sub(1).min(5)).min(MAX_RECONNECT_DELAY)
+}
+
+// read env variables and listen Hyprland unix socket
+fn main() {
+    // to see logs in output: add env RUST_LOG='debug'
+    env_logger::init();
+    // with arguments we are a client of the running daemon
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        std::process::exit(cli::run(&instance_name(), &args));
     }
+    let instance_sock = SingleInstance::new(&instance_name()).unwrap();
+    if !instance_sock.is_single() {
+        println!("Another instance is running.");
+        std::process::exit(1);
+    }
+    let daemon = Daemon::new();
+    // signals should be blocked before other threads are started
+    handle_signals(daemon.clone());
+    let control_daemon = daemon.clone();
+    if let Err(e) = instance_sock.serve(move |line| control::handle(&control_daemon, line)) {
+        log::warn!("Failed to start control socket: {}", e);
+    }
+    watch_options(&options_path(), daemon.clone());
+    refresh_keyboards_periodically(daemon.clone());
 
-    match env::var("HYPRLAND_INSTANCE_SIGNATURE") {
-        Ok(hypr_inst) => {
-            let default_socket = format!("/tmp/hypr/{hypr_inst}/.socket2.sock"); // for backawards compatibility
-            let socket = match env::var("XDG_RUNTIME_DIR") {
-                Ok(runtime_dir) => {
-                    match std::fs::metadata(format!("{runtime_dir}/hypr/{hypr_inst}/.socket2.sock"))
-                    {
-                        Ok(_) => format!("{runtime_dir}/hypr/{hypr_inst}/.socket2.sock"),
-                        Err(..) => default_socket,
+    let mut reconnect = false;
+    let mut failures = 0;
+    loop {
+        match Hyprctl::resolve() {
+            Ok(hyprctl) if discover_layouts(&daemon, &hyprctl, reconnect) => {
+                daemon.set_instance(hyprctl);
+                if reconnect {
+                    // layouts may be changed, working options are kept if the file is broken now
+                    if let Err(e) = daemon.reload_options() {
+                        println!("{e}");
+                        println!("Keep using previous options");
                     }
+                } else {
+                    daemon.load_options();
+                }
+                let connected_at = Instant::now();
+                // listen Hyprland socket
+                match daemon.listen() {
+                    Ok(()) => log::warn!("Hyprland socket closed"),
+                    Err(e) => log::warn!("Error {e}"),
+                }
+                if connected_at.elapsed() > HEALTHY_CONNECTION {
+                    failures = 0;
                 }
-                Err(..) => default_socket,
-            };
-            // listen Hyprland socket
-            match listen(socket) {
-                Ok(()) => {}
-                Err(e) => log::warn!("Error {e}"),
             }
+            Ok(_) => log::warn!("Hyprland is not ready"),
+            Err(e) => {
+                if !reconnect {
+                    println!("Fatal Error: {e}");
+                    std::process::exit(1);
+                }
+                log::warn!("{e}");
+            }
+        }
+        reconnect = true;
+        failures += 1;
+        if failures > MAX_RECONNECTS {
+            println!("Could not reconnect to Hyprland after {MAX_RECONNECTS} attempts");
+            daemon.save_learned();
+            std::process::exit(1);
         }
-        Err(e) => println!("Fatal Error: Hyprland is not run. {e}"),
+        let delay = reconnect_delay(failures);
+        log::warn!("Reconnecting to Hyprland in {delay}s");
+        std::thread::sleep(Duration::from_secs(delay));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reconnect_delay_grows_to_max() {
+        let delays: Vec<u64> = (1..=8).map(reconnect_delay).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30, 30]);
     }
-    std::process::exit(1);
 }
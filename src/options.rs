@@ -1,26 +1,200 @@
-// read and represent the options file
-// located at ~/.config/hyprland-per-window-layout/options.toml
+//! read and represent the options file
+//! located at ~/.config/hyprland-per-window-layout/options.toml
 
 use std::fs::File;
+use std::path::PathBuf;
 
-use serde::Deserialize;
 use std::collections::HashMap;
 use toml::Table;
 
-#[derive(Deserialize, Debug)]
+use crate::layouts::{KbLayouts, LayoutRef};
+use crate::rules::{parse_rule, MonitorRule, NamePattern, Rule, WorkspaceRule};
+use crate::validate::{check_options, has_errors};
+
+/// what a learned layout is bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MemoryMode {
+    /// each window remembers its own layout
+    Window,
+    /// all windows of the same class share a layout
+    Class,
+    /// all windows on the same workspace share a layout
+    Workspace,
+    /// one layout for everything, switching windows never changes it
+    Global,
+}
+
+impl MemoryMode {
+    /// name used in options.toml
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemoryMode::Window => "window",
+            MemoryMode::Class => "class",
+            MemoryMode::Workspace => "workspace",
+            MemoryMode::Global => "global",
+        }
+    }
+
+    /// parse a name used in options.toml, None if unknown
+    pub fn parse(value: &str) -> Option<MemoryMode> {
+        match value {
+            "window" => Some(MemoryMode::Window),
+            "class" => Some(MemoryMode::Class),
+            "workspace" => Some(MemoryMode::Workspace),
+            "global" => Some(MemoryMode::Global),
+            _ => None,
+        }
+    }
+}
+
+/// layout of a new window without a rule or a learned layout
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Fallback {
+    /// the first layout of kb_layout
+    First,
+    /// the layout that is active when the window is focused
+    KeepCurrent,
+    /// the layout of the window of the same class used last
+    LastUsedForClass,
+    /// the given layout
+    Layout(LayoutRef),
+}
+
+impl Fallback {
+    /// "first", "keep-current", "last-used-for-class" or a layout
+    pub(crate) fn from_value(value: &toml::Value) -> Result<Fallback, String> {
+        match value.as_str() {
+            Some("first") => Ok(Fallback::First),
+            Some("keep-current") => Ok(Fallback::KeepCurrent),
+            Some("last-used-for-class") => Ok(Fallback::LastUsedForClass),
+            _ => LayoutRef::from_value(value).map(Fallback::Layout),
+        }
+    }
+}
+
+/// everything set in options.toml
+#[derive(Debug)]
+#[non_exhaustive]
 pub struct Options {
-    pub keyboards: Vec<String>, // list of keyboards to switch layouts on
-    pub default_layouts: HashMap<u16, Vec<String>>, // default layouts for window classes
+    /// list of keyboards to switch layouts on
+    pub keyboards: Vec<String>,
+    /// default layouts for windows, first match wins
+    pub rules: Vec<Rule>,
+    /// default layouts for windows on a workspace, used if no rule matches
+    pub workspace_layouts: Vec<WorkspaceRule>,
+    /// default layouts for windows on a monitor, used if no workspace layout matches
+    pub monitor_layouts: Vec<MonitorRule>,
+    /// remember learned layouts between restarts
+    pub persist: bool,
+    /// remember learned layouts by initial window title too
+    pub persist_by_title: bool,
+    /// what a learned layout is bound to
+    pub memory_mode: MemoryMode,
+    /// new windows take the layout of the previously focused window of the same app
+    pub inherit_layout: bool,
+    /// check title rules when a window title changes, remember layouts per title
+    pub track_titles: bool,
+    /// layout of a new window nothing else applies to
+    pub fallback: Fallback,
+    /// keyboards never switched and whose layout changes are not remembered
+    pub ignore_keyboards: Vec<NamePattern>,
+    /// layouts not added to the layouts list, ex: of on-screen keyboards
+    pub ignore_layouts: Vec<NamePattern>,
+}
+
+/// hardware tokens and virtual keyboards
+pub const DEFAULT_IGNORE_KEYBOARDS: &[&str] = &["*yubikey*", "*wlr_virtual_keyboard_v*"];
+/// layout of the wvkbd on-screen keyboard
+pub const DEFAULT_IGNORE_LAYOUTS: &[&str] = &["wvkbd"];
+
+fn default_patterns(patterns: &[&str]) -> Vec<NamePattern> {
+    patterns
+        .iter()
+        .filter_map(|pattern| NamePattern::parse(pattern).ok())
+        .collect()
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            keyboards: Vec::new(),
+            rules: Vec::new(),
+            workspace_layouts: Vec::new(),
+            monitor_layouts: Vec::new(),
+            persist: false,
+            persist_by_title: false,
+            memory_mode: MemoryMode::Window,
+            inherit_layout: false,
+            track_titles: false,
+            fallback: Fallback::First,
+            ignore_keyboards: default_patterns(DEFAULT_IGNORE_KEYBOARDS),
+            ignore_layouts: default_patterns(DEFAULT_IGNORE_LAYOUTS),
+        }
+    }
+}
+
+impl Options {
+    /// replace layout names in rules with indices
+    /// rules with unknown layout names are removed
+    pub fn resolve_layouts(&mut self, kb: &KbLayouts, seen: &[String]) {
+        self.rules
+            .retain_mut(|rule| resolve_layout(&mut rule.layout, kb, seen));
+        self.workspace_layouts
+            .retain_mut(|rule| resolve_layout(&mut rule.layout, kb, seen));
+        self.monitor_layouts
+            .retain_mut(|rule| resolve_layout(&mut rule.layout, kb, seen));
+        if let Fallback::Layout(layout) = &mut self.fallback {
+            if !resolve_layout(layout, kb, seen) {
+                self.fallback = Fallback::First;
+            }
+        }
+    }
 }
 
-// function to read the options file toml
-pub fn read_options() -> Options {
-    // get the path to the options file
-    // in $HOME/.config/hyprland-per-window-layout/options.toml
-    let options_path = dirs::config_dir()
+// returns false if the layout name is unknown
+fn resolve_layout(layout: &mut LayoutRef, kb: &KbLayouts, seen: &[String]) -> bool {
+    match kb.resolve(layout, seen) {
+        Ok(index) => {
+            *layout = LayoutRef::Index(index);
+            true
+        }
+        Err(e) => {
+            println!("Error in options.toml: {e}");
+            false
+        }
+    }
+}
+
+/// get the path to the options file
+/// in $HOME/.config/hyprland-per-window-layout/options.toml
+pub fn options_path() -> PathBuf {
+    dirs::config_dir()
         .unwrap()
         .join("hyprland-per-window-layout")
-        .join("options.toml");
+        .join("options.toml")
+}
+
+/// function to read the options file toml
+/// problems are printed, broken parts are ignored
+pub fn read_options(kb: &KbLayouts, seen: &[String]) -> Options {
+    match load_options_file(kb, seen, false) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{e}");
+            Options::default()
+        }
+    }
+}
+
+/// read the options file toml, fails if it has any errors
+pub fn try_read_options(kb: &KbLayouts, seen: &[String]) -> Result<Options, String> {
+    load_options_file(kb, seen, true)
+}
+
+fn load_options_file(kb: &KbLayouts, seen: &[String], strict: bool) -> Result<Options, String> {
+    let options_path = options_path();
     // read the file contents if it exists
     // ignore if it doesn't exist
     match File::open(&options_path) {
@@ -29,64 +203,164 @@ pub fn read_options() -> Options {
             let file_content = match std::fs::read_to_string(&options_path) {
                 Ok(content) => content,
                 Err(e) => {
-                    println!("Error reading options.toml: {e}");
-                    return Options {
-                        keyboards: Vec::new(),
-                        default_layouts: HashMap::new(),
-                    };
+                    return Err(format!("Error reading options.toml: {e}"));
                 }
             };
-            let _t = match file_content.parse::<Table>() {
-                Ok(table) => table,
-                Err(e) => {
-                    println!("Error parsing options.toml: {e}");
-                    return Options {
-                        keyboards: Vec::new(),
-                        default_layouts: HashMap::new(),
-                    };
-                }
-            };
-            let mut map = HashMap::new();
-            let mut keyboards = Vec::new();
-            if let Some(_default_layouts) = _t.get("default_layouts") {
-                if let Some(default_layouts_array) = _default_layouts.as_array() {
-                    if let Some(first_layout) = default_layouts_array.first() {
-                        if let Some(layout_table) = first_layout.as_table() {
-                            for (key, value) in layout_table.iter() {
-                                if let Ok(key_num) = key.parse::<u16>() {
-                                    if let Some(value_array) = value.as_array() {
-                                        let layout_vec: Vec<String> = value_array
-                                            .iter()
-                                            .filter_map(|x| x.as_str().map(|s| s.to_string()))
-                                            .collect();
-                                        map.insert(key_num, layout_vec);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+            let problems = check_options(&file_content, kb, seen);
+            for problem in problems.iter() {
+                println!("options.toml:{problem}");
             }
-            if let Some(_keyboards) = _t.get("keyboards") {
-                if let Some(keyboards_array) = _keyboards.as_array() {
-                    for keyboard in keyboards_array.iter() {
-                        if let Some(keyboard_str) = keyboard.as_str() {
-                            keyboards.push(keyboard_str.to_string());
-                        }
-                    }
-                }
+            if strict && has_errors(&problems) {
+                return Err("Errors found in options.toml".to_string());
             }
-            return Options {
-                keyboards,
-                default_layouts: map,
-            };
+            parse_options(&file_content)
         }
         Err(_) => {
             println!("options.toml not found, using defaults");
+            Ok(Options::default())
+        }
+    }
+}
+
+/// build options from the file content
+/// values of wrong type are ignored, use check_options to report them
+pub fn parse_options(file_content: &str) -> Result<Options, String> {
+    let _t = match file_content.parse::<Table>() {
+        Ok(table) => table,
+        Err(e) => {
+            return Err(format!("Error parsing options.toml: {e}"));
         }
     };
-    Options {
-        keyboards: Vec::new(),
-        default_layouts: HashMap::new(),
+    let mut map = HashMap::new();
+    let mut keyboards = Vec::new();
+    let mut rules = Vec::new();
+    if let Some(_rules) = _t.get("rules") {
+        if let Some(rules_array) = _rules.as_array() {
+            for rule in rules_array.iter() {
+                if let Ok(rule) = parse_rule(rule) {
+                    rules.push(rule);
+                }
+            }
+        }
+    }
+    if let Some(_default_layouts) = _t.get("default_layouts") {
+        // [[default_layouts]] is an array of tables, [default_layouts] is a table
+        let tables = match _default_layouts {
+            toml::Value::Array(array) => array.iter().filter_map(|x| x.as_table()).collect(),
+            toml::Value::Table(table) => vec![table],
+            _ => Vec::new(),
+        };
+        for layout_table in tables {
+            for (key, value) in layout_table.iter() {
+                if let Some(value_array) = value.as_array() {
+                    let layout_vec: Vec<String> = value_array
+                        .iter()
+                        .filter_map(|x| x.as_str().map(|s| s.to_string()))
+                        .collect();
+                    map.entry(key.to_string())
+                        .or_insert_with(Vec::new)
+                        .extend(layout_vec);
+                }
+            }
+        }
+    }
+    // [[default_layouts]] are checked after [[rules]], as exact class matches
+    // layout indices go first in numeric order, then layout names
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort_by_key(|key| (key.parse::<u16>().unwrap_or(u16::MAX), key.to_string()));
+    for key in keys {
+        for class in map[key].iter() {
+            if let Ok(rule) = Rule::exact_class(class, LayoutRef::from_key(key)) {
+                rules.push(rule);
+            }
+        }
+    }
+    // [workspace_layouts]
+    // "name:chat" = "ua"
+    let mut workspace_layouts = Vec::new();
+    if let Some(table) = _t.get("workspace_layouts").and_then(|x| x.as_table()) {
+        for (workspace, value) in table.iter() {
+            if let Ok(layout) = LayoutRef::from_value(value) {
+                workspace_layouts.push(WorkspaceRule {
+                    workspace: workspace.to_string(),
+                    layout,
+                });
+            }
+        }
+    }
+    // [monitor_layouts]
+    // "eDP-1" = "us"
+    // "desc:Dell Inc. DELL U2720Q" = "de"
+    let mut monitor_layouts = Vec::new();
+    if let Some(table) = _t.get("monitor_layouts").and_then(|x| x.as_table()) {
+        for (monitor, value) in table.iter() {
+            if let Ok(layout) = LayoutRef::from_value(value) {
+                monitor_layouts.push(MonitorRule {
+                    monitor: monitor.to_string(),
+                    layout,
+                });
+            }
+        }
+    }
+    if let Some(_keyboards) = _t.get("keyboards") {
+        if let Some(keyboards_array) = _keyboards.as_array() {
+            for keyboard in keyboards_array.iter() {
+                if let Some(keyboard_str) = keyboard.as_str() {
+                    keyboards.push(keyboard_str.to_string());
+                }
+            }
+        }
     }
+    let defaults = Options::default();
+    let persist = _t
+        .get("persist")
+        .and_then(|x| x.as_bool())
+        .unwrap_or(defaults.persist);
+    let persist_by_title = _t
+        .get("persist_by_title")
+        .and_then(|x| x.as_bool())
+        .unwrap_or(defaults.persist_by_title);
+    let memory_mode = _t
+        .get("memory_mode")
+        .and_then(|x| x.as_str())
+        .and_then(MemoryMode::parse)
+        .unwrap_or(defaults.memory_mode);
+    let inherit_layout = _t
+        .get("inherit_layout")
+        .and_then(|x| x.as_bool())
+        .unwrap_or(defaults.inherit_layout);
+    let track_titles = _t
+        .get("track_titles")
+        .and_then(|x| x.as_bool())
+        .unwrap_or(defaults.track_titles);
+    let fallback = _t
+        .get("fallback")
+        .and_then(|x| Fallback::from_value(x).ok())
+        .unwrap_or(defaults.fallback);
+    // a list replaces the defaults, use [] to ignore nothing
+    let patterns =
+        |key: &str, default: Vec<NamePattern>| match _t.get(key).and_then(|x| x.as_array()) {
+            Some(array) => array
+                .iter()
+                .filter_map(|x| x.as_str())
+                .filter_map(|pattern| NamePattern::parse(pattern).ok())
+                .collect(),
+            None => default,
+        };
+    let ignore_keyboards = patterns("ignore_keyboards", defaults.ignore_keyboards);
+    let ignore_layouts = patterns("ignore_layouts", defaults.ignore_layouts);
+    Ok(Options {
+        keyboards,
+        rules,
+        workspace_layouts,
+        monitor_layouts,
+        persist,
+        persist_by_title,
+        memory_mode,
+        inherit_layout,
+        track_titles,
+        fallback,
+        ignore_keyboards,
+        ignore_layouts,
+    })
 }
@@ -0,0 +1,89 @@
+//! processes of windows, read from /proc
+
+use std::fs;
+
+// fields of /proc/<pid>/stat we need
+#[derive(Debug, PartialEq)]
+struct Stat {
+    parent_pid: i32,
+    // controlling terminal, 0 if none
+    tty: i32,
+    // foreground process group of the terminal
+    tpgid: i32,
+}
+
+fn read_stat(pid: i32) -> Option<Stat> {
+    parse_stat(&fs::read_to_string(format!("/proc/{pid}/stat")).ok()?)
+}
+
+// "1234 (comm) S 1000 1234 1234 34816 1300 ..." where comm may have spaces and parentheses
+// after comm: state, ppid, pgrp, session, tty_nr, tpgid
+fn parse_stat(stat: &str) -> Option<Stat> {
+    let (_, rest) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    Some(Stat {
+        parent_pid: fields.get(1)?.parse().ok()?,
+        tty: fields.get(4)?.parse().ok()?,
+        tpgid: fields.get(5)?.parse().ok()?,
+    })
+}
+
+/// parent pid of a process from `/proc/<pid>/stat`
+pub fn parent_pid(pid: i32) -> Option<i32> {
+    Some(read_stat(pid)?.parent_pid)
+}
+
+/// command name of a process, ex: nvim
+pub fn command_name(pid: i32) -> Option<String> {
+    let comm = fs::read_to_string(format!("/proc/{pid}/comm")).ok()?;
+    Some(comm.trim_end().to_string())
+}
+
+// direct children of a process, from /proc/<pid>/task/*/children
+fn children(pid: i32) -> Vec<i32> {
+    let mut children = Vec::new();
+    if let Ok(tasks) = fs::read_dir(format!("/proc/{pid}/task")) {
+        for task in tasks.flatten() {
+            if let Ok(list) = fs::read_to_string(task.path().join("children")) {
+                children.extend(
+                    list.split_whitespace()
+                        .filter_map(|c| c.parse::<i32>().ok()),
+                );
+            }
+        }
+    }
+    children
+}
+
+/// command name of the foreground process in a terminal window, ex: nvim or zsh
+/// the shells of the terminal process are checked, the latest started foreground job wins
+/// None if the process has no shells, not a terminal
+pub fn foreground_process(pid: i32) -> Option<String> {
+    let foreground = children(pid)
+        .into_iter()
+        .filter_map(read_stat)
+        .filter(|stat| stat.tty != 0 && stat.tpgid > 0)
+        .map(|stat| stat.tpgid)
+        .max()?;
+    // the process group id is the pid of its leader
+    command_name(foreground)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_is_read_after_command_name() {
+        let stat = "4321 (Web Content (x)) S 1234 4321 4321 34816 4400 4194560";
+        assert_eq!(
+            parse_stat(stat),
+            Some(Stat {
+                parent_pid: 1234,
+                tty: 34816,
+                tpgid: 4400,
+            })
+        );
+        assert_eq!(parse_stat("garbage"), None);
+    }
+}
@@ -0,0 +1,214 @@
+//! rules to pick a default layout for a new window
+//! matched against class, initial class, title and initial title
+
+use regex::Regex;
+use toml::Value;
+
+use crate::layouts::LayoutRef;
+
+/// what we know about a window when a rule is checked
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct WindowInfo {
+    /// current class, ex: org.telegram.desktop
+    pub class: String,
+    /// current title
+    pub title: String,
+    /// class when the window was opened
+    pub initial_class: String,
+    /// title when the window was opened
+    pub initial_title: String,
+    /// from "j/clients" request, None for windows seen only in events
+    pub pid: Option<i32>,
+    /// parent of the window process, to find windows spawned by other windows
+    pub parent_pid: Option<i32>,
+    /// foreground process of a terminal window, ex: nvim, empty if unknown
+    pub process: String,
+}
+
+/// `[[rules]]` entry, all its patterns should match a window
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Rule {
+    pub(crate) class: Option<Regex>,
+    pub(crate) initial_class: Option<Regex>,
+    pub(crate) title: Option<Regex>,
+    pub(crate) initial_title: Option<Regex>,
+    /// foreground process of a terminal window
+    pub(crate) process: Option<Regex>,
+    /// layout for matching windows
+    pub layout: LayoutRef,
+}
+
+impl Rule {
+    /// rule for exact class name, used for `[[default_layouts]]` entries
+    pub(crate) fn exact_class(class: &str, layout: LayoutRef) -> Result<Rule, regex::Error> {
+        Ok(Rule {
+            class: Some(Regex::new(&format!("^{}$", regex::escape(class)))?),
+            initial_class: None,
+            title: None,
+            initial_title: None,
+            process: None,
+            layout,
+        })
+    }
+
+    /// all given patterns should match
+    pub fn matches(&self, window: &WindowInfo) -> bool {
+        let checks = [
+            (&self.class, &window.class),
+            (&self.initial_class, &window.initial_class),
+            (&self.title, &window.title),
+            (&self.initial_title, &window.initial_title),
+            (&self.process, &window.process),
+        ];
+        checks
+            .iter()
+            .all(|(pattern, value)| pattern.as_ref().is_none_or(|re| re.is_match(value)))
+    }
+}
+
+/// default layout for windows on a workspace, from `[workspace_layouts]`
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct WorkspaceRule {
+    /// "name:chat", a workspace id like "3" or a plain name
+    pub workspace: String,
+    /// layout for windows on the workspace
+    pub layout: LayoutRef,
+}
+
+impl WorkspaceRule {
+    /// check a workspace by its id (if known) and name
+    pub fn matches(&self, id: Option<i64>, name: &str) -> bool {
+        if let Some(wanted) = self.workspace.strip_prefix("name:") {
+            return wanted == name;
+        }
+        match (self.workspace.parse::<i64>(), id) {
+            (Ok(wanted), Some(id)) => wanted == id,
+            _ => self.workspace == name,
+        }
+    }
+}
+
+/// what we know about a monitor, from "j/monitors" or monitoraddedv2 event
+#[derive(Debug, Clone, Default, PartialEq)]
+#[non_exhaustive]
+pub struct MonitorInfo {
+    /// connector name, ex: eDP-1
+    pub name: String,
+    /// ex: Dell Inc. DELL U2720Q 1234567
+    pub description: String,
+    /// true for the monitor with the active workspace
+    pub focused: bool,
+}
+
+/// default layout for windows on a monitor, from `[monitor_layouts]`
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct MonitorRule {
+    /// connector name like "eDP-1" or "desc:" and the beginning of the description
+    pub monitor: String,
+    /// layout for windows on the monitor
+    pub layout: LayoutRef,
+}
+
+impl MonitorRule {
+    /// check a monitor by its connector name and description
+    pub fn matches(&self, name: &str, description: &str) -> bool {
+        match self.monitor.strip_prefix("desc:") {
+            Some(wanted) => !description.is_empty() && description.starts_with(wanted.trim()),
+            None => self.monitor == name,
+        }
+    }
+}
+
+/// keyboard or layout name pattern, from `ignore_keyboards` and `ignore_layouts`
+/// a glob like "*yubikey*" or a regex with "re:" prefix
+#[derive(Debug, Clone)]
+pub struct NamePattern(Regex);
+
+impl NamePattern {
+    /// parse a glob or a regex with "re:" prefix
+    pub fn parse(pattern: &str) -> Result<NamePattern, String> {
+        let re = match pattern.strip_prefix("re:") {
+            Some(re) => re.to_string(),
+            None => glob_to_regex(pattern),
+        };
+        Regex::new(&re).map(NamePattern).map_err(|e| e.to_string())
+    }
+
+    /// the whole name should match a glob, a regex may match a part of it
+    pub fn matches(&self, name: &str) -> bool {
+        self.0.is_match(name)
+    }
+}
+
+// "*" is any text, "?" is any character, the whole name should match
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+/// true if any pattern matches the name
+pub fn is_ignored(patterns: &[NamePattern], name: &str) -> bool {
+    patterns.iter().any(|pattern| pattern.matches(name))
+}
+
+/// first matching rule wins
+pub fn find_layout(rules: &[Rule], window: &WindowInfo) -> Option<u16> {
+    let rule = rules.iter().find(|rule| rule.matches(window))?;
+    log::debug!("Rule {:?} matched window {:?}", rule, window);
+    rule.layout.index()
+}
+
+/// parse a `[[rules]]` entry from options.toml
+/// example:
+/// ```toml
+/// [[rules]]
+/// class = "^jetbrains-.*"
+/// layout = 1 # or layout = "de"
+/// ```
+pub(crate) fn parse_rule(value: &Value) -> Result<Rule, String> {
+    let table = value.as_table().ok_or("rule should be a table")?;
+    let layout = match table.get("layout") {
+        Some(value) => LayoutRef::from_value(value)?,
+        None => return Err("layout is missing".to_string()),
+    };
+    let pattern = |key: &str| -> Result<Option<Regex>, String> {
+        match table.get(key) {
+            Some(Value::String(re)) => Regex::new(re)
+                .map(Some)
+                .map_err(|e| format!("bad regex in {key}: {e}")),
+            Some(_) => Err(format!("{key} should be a string")),
+            None => Ok(None),
+        }
+    };
+    let rule = Rule {
+        class: pattern("class")?,
+        initial_class: pattern("initial_class")?,
+        title: pattern("title")?,
+        initial_title: pattern("initial_title")?,
+        process: pattern("process")?,
+        layout,
+    };
+    if rule.class.is_none()
+        && rule.initial_class.is_none()
+        && rule.title.is_none()
+        && rule.initial_title.is_none()
+        && rule.process.is_none()
+    {
+        return Err(
+            "rule should have class, initial_class, title, initial_title or process".to_string(),
+        );
+    }
+    Ok(rule)
+}
@@ -3,10 +3,12 @@ extern crate nix;
 pub use self::inner::*;
 
 mod inner {
-    use nix::sys::socket::{self, UnixAddr};
+    use nix::sys::socket::{self, sockopt, UnixAddr};
     use nix::unistd;
     use nix::Result;
-    use std::os::unix::prelude::RawFd;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::os::unix::prelude::{FromRawFd, RawFd};
 
     /// A struct representing one running instance.
     pub struct SingleInstance {
@@ -37,6 +39,75 @@ mod inner {
         pub fn is_single(&self) -> bool {
             self.maybe_sock.is_some()
         }
+
+        /// Accepts connections on the instance socket in a background thread.
+        /// Each line received is passed to the handler and its reply is sent back.
+        pub fn serve<F>(&self, handler: F) -> Result<()>
+        where
+            F: Fn(&str) -> String + Send + Sync + 'static,
+        {
+            let sock = match self.maybe_sock {
+                Some(sock) => sock,
+                None => return Err(nix::errno::Errno::EBADF),
+            };
+            socket::listen(sock, 8)?;
+            let handler = std::sync::Arc::new(handler);
+            std::thread::spawn(move || loop {
+                let fd = match socket::accept(sock) {
+                    Ok(fd) => fd,
+                    Err(e) => {
+                        log::warn!("Control socket accept failed: {}", e);
+                        continue;
+                    }
+                };
+                // the stream owns the fd and closes it when dropped
+                let stream = unsafe { UnixStream::from_raw_fd(fd) };
+                // abstract sockets have no file permissions, anyone could connect
+                if !same_user(fd) {
+                    log::warn!("Control socket connection from another user rejected");
+                    continue;
+                }
+                let handler = handler.clone();
+                std::thread::spawn(move || {
+                    let mut writer = match stream.try_clone() {
+                        Ok(writer) => writer,
+                        Err(_) => return,
+                    };
+                    for line in BufReader::new(stream).lines() {
+                        let line = match line {
+                            Ok(line) => line,
+                            Err(_) => break,
+                        };
+                        if line.trim().is_empty() {
+                            continue;
+                        }
+                        let reply = handler(&line);
+                        if writeln!(writer, "{reply}").is_err() {
+                            break;
+                        }
+                    }
+                });
+            });
+            Ok(())
+        }
+
+        /// Connects to the socket of the running instance.
+        pub fn connect(name: &str) -> std::io::Result<UnixStream> {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+            UnixStream::connect_addr(&addr)
+        }
+    }
+
+    // the peer of the connection runs as our user
+    fn same_user(fd: RawFd) -> bool {
+        match socket::getsockopt(fd, sockopt::PeerCredentials) {
+            Ok(credentials) => credentials.uid() == unistd::getuid().as_raw(),
+            Err(e) => {
+                log::warn!("Failed to get peer credentials: {}", e);
+                false
+            }
+        }
     }
 
     impl Drop for SingleInstance {
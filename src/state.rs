@@ -0,0 +1,1612 @@
+//! daemon state and its transitions
+//! events from Hyprland go in, actions to perform come out, no I/O happens here
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::events::HyprEvent;
+use crate::layouts::{KbLayouts, KeyboardInfo, LayoutRef};
+use crate::options::{Fallback, MemoryMode, Options};
+use crate::rules::{find_layout, is_ignored, MonitorInfo, WindowInfo};
+use crate::state_file::SavedState;
+
+/// something that happened
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// message from Hyprland socket
+    Hyprland(HyprEvent),
+    /// reply to Action::FetchWindow, None if the window wasn't found
+    WindowFetched {
+        /// address of the window, ex: 0x5632c5a16e80
+        addr: String,
+        /// class, titles and process of the window
+        info: Option<WindowInfo>,
+    },
+    /// Hyprland refused to switch layout on this keyboard
+    KeyboardRejected(String),
+    /// reply to "j/monitors" request, sent when connected to Hyprland
+    Monitors(Vec<MonitorInfo>),
+    /// windows from "j/clients" request, sent when connected to Hyprland
+    Clients {
+        /// addresses of open windows with what we know about them
+        windows: Vec<(String, WindowInfo)>,
+        /// address of the focused window
+        focused: Option<String>,
+    },
+    /// reply to Action::FetchProcess
+    ProcessFetched {
+        /// address of the window
+        addr: String,
+        /// command name of the foreground process, empty if the window is not a terminal
+        process: String,
+    },
+    /// connected keyboards from "j/devices" request
+    Keyboards(Vec<KeyboardInfo>),
+}
+
+impl From<HyprEvent> for Event {
+    fn from(event: HyprEvent) -> Event {
+        Event::Hyprland(event)
+    }
+}
+
+/// side effect to be performed by the caller
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Action {
+    /// switchxkblayout on each keyboard with its own index of the layout
+    SwitchLayout {
+        /// keyboard names with indices of the layout in their own layout lists
+        keyboards: Vec<(String, u16)>,
+        /// index of the layout in input:kb_layout
+        index: u16,
+    },
+    /// look up a window we haven't seen opened, answer with Event::WindowFetched
+    FetchWindow(String),
+    /// write learned layouts to the state file
+    SaveLearned,
+    /// list connected keyboards, answer with Event::Keyboards
+    FetchKeyboards,
+    /// find the foreground process of a terminal window, answer with Event::ProcessFetched
+    FetchProcess {
+        /// address of the window
+        addr: String,
+        /// pid of the window process
+        pid: i32,
+    },
+}
+
+/// /proc lookups needed for new windows, they are skipped when nothing uses them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ProcessLookups {
+    /// parent pid, to inherit layouts
+    pub parent_pid: bool,
+    /// foreground process of terminal windows, for process rules
+    pub process: bool,
+}
+
+/// layouts of windows and everything needed to pick them
+/// feed it events with [`State::handle`] and perform the returned actions
+#[derive(Debug, Default)]
+pub struct State {
+    options: Options,
+    /// layouts configured in Hyprland, used to resolve layout names
+    kb_layouts: KbLayouts,
+    /// layouts of windows, the key depends on memory_mode:
+    /// window address, class, workspace or global
+    memory: HashMap<String, u16>,
+    /// layouts (long names) in the order of kb_layout
+    layouts: Vec<String>,
+    /// keyboards to switch layout on
+    keyboards: Vec<String>,
+    /// keyboards added by discovery, only they are removed when unplugged
+    /// keyboards from options and 'activelayout' events are kept
+    discovered_keyboards: Vec<String>,
+    /// keyboards Hyprland refused to switch, not discovered again until they are unplugged
+    rejected_keyboards: Vec<String>,
+    /// short names of layouts of keyboards with their own layout list
+    keyboard_layouts: HashMap<String, Vec<String>>,
+    active_window: String,
+    /// focused before the active window, new windows may inherit its layout
+    previous_window: String,
+    active_class: String,
+    active_workspace: String,
+    // None until a workspacev2 event is received
+    active_workspace_id: Option<i64>,
+    /// opened special workspace (scratchpad), ex: special:term
+    active_special: Option<String>,
+    /// window and layout under the special workspace, restored when it is closed
+    under_special: Option<(String, u16)>,
+    active_title: String,
+    active_monitor: String,
+    active_layout: u16,
+    /// connected monitors: name => description
+    monitors: HashMap<String, String>,
+    /// known windows: address => class and titles
+    windows: HashMap<String, WindowInfo>,
+    /// layouts of windows before a process rule was applied, restored when the process exits
+    process_overrides: HashMap<String, u16>,
+    /// layouts chosen for titles of a window: address => title => layout
+    title_memory: HashMap<String, HashMap<String, u16>>,
+    /// layouts of windows before a title rule was applied, restored on a title without a rule
+    title_overrides: HashMap<String, u16>,
+    /// layout of the window of a class used last: class => layout
+    class_layouts: HashMap<String, u16>,
+    /// layouts set for a class by a command, used for windows opened later
+    class_pins: HashMap<String, u16>,
+    /// layouts learned by window class, persisted in the state file
+    /// None if persistence is disabled
+    learned: Option<SavedState>,
+}
+
+impl State {
+    /// state with default options, no layouts and no keyboards
+    pub fn new() -> State {
+        State::default()
+    }
+
+    /// update the state, returns actions the caller should perform
+    /// their results are fed back as events
+    pub fn handle(&mut self, event: Event) -> Vec<Action> {
+        log::debug!("{:?}", event);
+        match event {
+            Event::Hyprland(event) => self.hyprland_event(event),
+            Event::ProcessFetched { addr, process } => self.process_changed(&addr, process),
+            Event::WindowFetched { addr, info } => {
+                let info = match info {
+                    Some(info) => info,
+                    None => match self.windows.remove(&addr) {
+                        Some(known) => known,
+                        None => WindowInfo {
+                            class: self.active_class.clone(),
+                            title: self.active_title.clone(),
+                            initial_class: self.active_class.clone(),
+                            initial_title: self.active_title.clone(),
+                            ..Default::default()
+                        },
+                    },
+                };
+                let process = info.process.clone();
+                let known = self.windows.insert(addr.clone(), info);
+                // focus may have moved on while we were waiting
+                let mut actions = if addr == self.active_window {
+                    self.focus(&addr)
+                } else {
+                    vec![]
+                };
+                // a known window may have another foreground process now
+                if let Some(known) = known {
+                    if let Some(info) = self.windows.get_mut(&addr) {
+                        info.process = known.process;
+                    }
+                    actions.extend(self.process_changed(&addr, process));
+                }
+                actions
+            }
+            Event::KeyboardRejected(keyboard) => {
+                log::warn!("Keyboard removed from list: {}", keyboard);
+                self.keyboards.retain(|kb| *kb != keyboard);
+                self.discovered_keyboards.retain(|kb| *kb != keyboard);
+                if !self.rejected_keyboards.contains(&keyboard) {
+                    self.rejected_keyboards.push(keyboard);
+                }
+                vec![]
+            }
+            Event::Clients { windows, focused } => self.clients(windows, focused),
+            Event::Keyboards(connected) => {
+                let is_connected = |kb: &String| connected.iter().any(|k| k.name == *kb);
+                let (present, unplugged): (Vec<String>, Vec<String>) =
+                    self.discovered_keyboards.drain(..).partition(is_connected);
+                for kb in unplugged {
+                    log::debug!("Keyboard disconnected: {}", kb);
+                    self.keyboards.retain(|k| *k != kb);
+                }
+                self.discovered_keyboards = present;
+                self.rejected_keyboards.retain(is_connected);
+                self.keyboard_layouts.clear();
+                for keyboard in connected {
+                    if !keyboard.layouts.is_empty() {
+                        self.keyboard_layouts
+                            .insert(keyboard.name.clone(), keyboard.layouts);
+                    }
+                    if self.rejected_keyboards.contains(&keyboard.name) {
+                        continue;
+                    }
+                    let known = self.keyboards.contains(&keyboard.name);
+                    self.add_keyboard(keyboard.name.clone());
+                    if !known && self.keyboards.contains(&keyboard.name) {
+                        self.discovered_keyboards.push(keyboard.name);
+                    }
+                }
+                vec![]
+            }
+            Event::Monitors(monitors) => {
+                self.monitors.clear();
+                for monitor in monitors {
+                    if monitor.focused {
+                        self.active_monitor = monitor.name.clone();
+                    }
+                    self.monitors.insert(monitor.name, monitor.description);
+                }
+                vec![]
+            }
+        }
+    }
+
+    fn hyprland_event(&mut self, event: HyprEvent) -> Vec<Action> {
+        match event {
+            HyprEvent::ActiveWindow { class, title } => {
+                self.active_class = class;
+                self.active_title = title;
+            }
+            // v1 events come first, v2 events add workspace id
+            HyprEvent::Workspace { name } => {
+                self.active_workspace = name;
+                self.active_workspace_id = None;
+            }
+            HyprEvent::WorkspaceV2 { id, name } => {
+                self.active_workspace = name;
+                self.active_workspace_id = Some(id);
+            }
+            HyprEvent::FocusedMon { monitor, workspace } => {
+                self.active_monitor = monitor;
+                self.active_workspace = workspace;
+                self.active_workspace_id = None;
+            }
+            HyprEvent::FocusedMonV2 {
+                monitor,
+                workspace_id,
+            } => {
+                self.active_monitor = monitor;
+                self.active_workspace_id = Some(workspace_id);
+            }
+            HyprEvent::MonitorAddedV2 {
+                name, description, ..
+            } => {
+                self.monitors.insert(name, description);
+            }
+            HyprEvent::ActiveSpecial { name, .. } => return self.special_toggled(name),
+            HyprEvent::MonitorRemoved { name } => {
+                self.monitors.remove(&name);
+            }
+            HyprEvent::OpenWindow {
+                address,
+                class,
+                title,
+                ..
+            } => {
+                let info = WindowInfo {
+                    class: class.clone(),
+                    title: title.clone(),
+                    initial_class: class,
+                    initial_title: title,
+                    ..Default::default()
+                };
+                self.windows.insert(address, info);
+            }
+            HyprEvent::WindowTitleV2 { address, title } => {
+                let mut actions = self.title_changed(&address, title);
+                // shells set the title to the running command
+                // other windows are checked when they are focused
+                if address == self.active_window {
+                    actions.extend(self.fetch_process(&address));
+                }
+                return actions;
+            }
+            HyprEvent::ActiveWindowV2 { address: None } => {
+                self.set_active_window(String::new());
+                if self.options.memory_mode == MemoryMode::Workspace {
+                    return self.focus_empty_workspace();
+                }
+                log::debug!("No active window (empty workspace), maintaining current layout");
+            }
+            HyprEvent::ActiveWindowV2 {
+                address: Some(addr),
+            } => {
+                self.set_active_window(addr.clone());
+                let need_pid = self.should_inherit(&addr) || self.has_process_rules();
+                return match self.windows.get_mut(&addr) {
+                    // the process of the window is needed to inherit a layout or check it
+                    Some(info) if need_pid && info.pid.is_none() => {
+                        info.title = self.active_title.clone();
+                        vec![Action::FetchWindow(addr)]
+                    }
+                    Some(info) => {
+                        info.title = self.active_title.clone();
+                        let mut actions = self.focus(&addr);
+                        actions.extend(self.fetch_process(&addr));
+                        actions
+                    }
+                    // windows opened before we started are looked up with 'clients' request
+                    None => vec![Action::FetchWindow(addr)],
+                };
+            }
+            HyprEvent::CloseWindow { address } => {
+                // other modes keep the layout for windows that are still open or will be opened
+                if self.options.memory_mode == MemoryMode::Window {
+                    self.memory.remove(&address);
+                }
+                self.windows.remove(&address);
+                self.process_overrides.remove(&address);
+                self.title_memory.remove(&address);
+                self.title_overrides.remove(&address);
+            }
+            // input devices may be changed in hyprland.conf
+            HyprEvent::ConfigReloaded => return vec![Action::FetchKeyboards],
+            HyprEvent::ActiveLayout { keyboard, layout } => {
+                return self.layout_changed(keyboard, layout)
+            }
+            _ => {}
+        }
+        vec![]
+    }
+
+    // windows opened before we started or while we were disconnected
+    // rules are applied when they are focused
+    fn clients(
+        &mut self,
+        windows: Vec<(String, WindowInfo)>,
+        focused: Option<String>,
+    ) -> Vec<Action> {
+        log::debug!("{} windows open", windows.len());
+        for (addr, info) in windows {
+            self.windows.entry(addr).or_insert(info);
+        }
+        let addr = match focused {
+            Some(addr) if self.windows.contains_key(&addr) => addr,
+            _ => return vec![],
+        };
+        if let Some(info) = self.windows.get(&addr) {
+            self.active_class = info.class.clone();
+            self.active_title = info.title.clone();
+        }
+        self.active_window = addr.clone();
+        let key = self.memory_key(&addr);
+        if self.memory.contains_key(&key) {
+            // reconnected, restore the layout we remember
+            return self.focus(&addr);
+        }
+        // the user may have chosen the layout before we started, keep it
+        log::debug!("Keep current layout {} for {}", self.active_layout, key);
+        self.memory.insert(key, self.active_layout);
+        vec![]
+    }
+
+    // apply the layout of the focused window
+    fn focus(&mut self, addr: &str) -> Vec<Action> {
+        // rules win over the layout shared by the workspace
+        if self.options.memory_mode == MemoryMode::Workspace {
+            if let Some(index) = self.default_layout(addr) {
+                log::debug!("Found default layout {} for window {}", index, addr);
+                return self.switch_if_needed(index);
+            }
+        }
+        let key = self.memory_key(addr);
+        if let Some(index) = self.memory.get(&key) {
+            log::debug!("{}: {}", key, index);
+            return self.switch_if_needed(*index);
+        }
+        log::debug!("added addr: {}", addr);
+        // check if the window class was pinned to a layout
+        // then if the window was opened from the previous one
+        // then if we have default layout for this window
+        // then if we have learned layout for this window class
+        // then if we have default layout for its workspace
+        let index = if let Some(index) = self.pinned_layout(addr) {
+            log::debug!("Found pinned layout {} for window {}", index, addr);
+            index
+        } else if let Some(index) = self.inherited_layout(addr) {
+            log::debug!(
+                "Window {} inherits layout {} from {}",
+                addr,
+                index,
+                self.previous_window
+            );
+            index
+        } else if let Some(index) = self.default_layout(addr) {
+            log::debug!("Found default layout {} for window {}", index, addr);
+            index
+        } else if let Some(index) = self.learned_layout(addr) {
+            log::debug!("Found learned layout {} for window {}", index, addr);
+            index
+        } else if let Some(index) = self.workspace_layout() {
+            log::debug!(
+                "Found default layout {} for workspace {}",
+                index,
+                self.workspace().1
+            );
+            index
+        } else if let Some(index) = self.monitor_layout() {
+            log::debug!(
+                "Found default layout {} for monitor {}",
+                index,
+                self.active_monitor
+            );
+            index
+        } else {
+            self.fallback_layout(addr)
+        };
+        self.memory.insert(key, index);
+        self.switch_if_needed(index)
+    }
+
+    fn pinned_layout(&self, addr: &str) -> Option<u16> {
+        let info = self.windows.get(addr)?;
+        self.class_pins.get(&info.class).copied()
+    }
+
+    // nothing is known about the window, use the fallback option
+    fn fallback_layout(&self, addr: &str) -> u16 {
+        match &self.options.fallback {
+            Fallback::KeepCurrent => self.active_layout,
+            Fallback::LastUsedForClass => self
+                .windows
+                .get(addr)
+                .and_then(|info| self.class_layouts.get(&info.class))
+                .copied()
+                .unwrap_or(0),
+            Fallback::Layout(LayoutRef::Index(index)) => *index,
+            // set layout to default one (index 0)
+            _ => 0,
+        }
+    }
+
+    // remember the layout used in the active window for its class
+    fn note_class_layout(&mut self, index: u16) {
+        if let Some(info) = self.windows.get(&self.active_window) {
+            if !info.class.is_empty() {
+                self.class_layouts.insert(info.class.clone(), index);
+            }
+        }
+    }
+
+    // Hyprland sends activespecial before focusing a window on the special workspace
+    // so the active window is still the one under it
+    fn special_toggled(&mut self, name: String) -> Vec<Action> {
+        if !name.is_empty() {
+            log::debug!("Special workspace {} opened", name);
+            if self.active_special.is_none() {
+                self.under_special = Some((self.active_window.clone(), self.active_layout));
+            }
+            self.active_special = Some(name);
+            return vec![];
+        }
+        if self.active_special.take().is_none() {
+            return vec![];
+        }
+        log::debug!("Special workspace closed");
+        // focus may not move when the workspace under it is empty
+        // so the layout of the scratchpad would stay active
+        match self.under_special.take() {
+            Some((addr, _)) if self.windows.contains_key(&addr) => {
+                self.active_window = addr.clone();
+                self.focus(&addr)
+            }
+            // the scratchpad window is not focused anymore,
+            // its class shouldn't get the restored layout
+            Some((_, index)) => {
+                self.set_active_window(String::new());
+                self.switch_if_needed(index)
+            }
+            None => vec![],
+        }
+    }
+
+    // focused workspace id (if known) and name, a special workspace if opened
+    fn workspace(&self) -> (Option<i64>, &str) {
+        match &self.active_special {
+            Some(name) => (None, name),
+            None => (self.active_workspace_id, &self.active_workspace),
+        }
+    }
+
+    fn has_process_rules(&self) -> bool {
+        self.options.rules.iter().any(|rule| rule.process.is_some())
+    }
+
+    pub(crate) fn process_lookups(&self) -> ProcessLookups {
+        ProcessLookups {
+            parent_pid: self.options.inherit_layout,
+            process: self.has_process_rules(),
+        }
+    }
+
+    // check the foreground process of a terminal window again
+    fn fetch_process(&self, addr: &str) -> Option<Action> {
+        if !self.has_process_rules() {
+            return None;
+        }
+        let pid = self.windows.get(addr)?.pid?;
+        Some(Action::FetchProcess {
+            addr: addr.to_string(),
+            pid,
+        })
+    }
+
+    // apply a process rule when a program starts in a terminal window,
+    // restore the previous layout when it exits
+    fn process_changed(&mut self, addr: &str, process: String) -> Vec<Action> {
+        let info = match self.windows.get_mut(addr) {
+            Some(info) if info.process != process => info,
+            _ => return vec![],
+        };
+        log::debug!("Window {} runs {}", addr, process);
+        info.process = process;
+        let rule_layout = self
+            .options
+            .rules
+            .iter()
+            .filter(|rule| rule.process.is_some())
+            .find(|rule| rule.matches(info))
+            .and_then(|rule| rule.layout.index());
+        let key = self.memory_key(addr);
+        let index = match rule_layout {
+            Some(index) => {
+                if let Some(current) = self.memory.get(&key) {
+                    self.process_overrides
+                        .entry(addr.to_string())
+                        .or_insert(*current);
+                }
+                index
+            }
+            None => match self.process_overrides.remove(addr) {
+                Some(previous) => previous,
+                None => return vec![],
+            },
+        };
+        self.memory.insert(key, index);
+        if addr == self.active_window {
+            self.switch_if_needed(index)
+        } else {
+            vec![]
+        }
+    }
+
+    fn set_active_window(&mut self, addr: String) {
+        if addr != self.active_window && !self.active_window.is_empty() {
+            self.previous_window = std::mem::take(&mut self.active_window);
+        }
+        self.active_window = addr;
+    }
+
+    // a window without a layout yet, it may inherit one
+    fn should_inherit(&self, addr: &str) -> bool {
+        self.options.inherit_layout && !self.memory.contains_key(&self.memory_key(addr))
+    }
+
+    // layout of the previously focused window if the new one belongs to the same app:
+    // the same process or class, or it was spawned by the previous window process
+    fn inherited_layout(&self, addr: &str) -> Option<u16> {
+        // rules are explicit, they win
+        if !self.options.inherit_layout || self.default_layout(addr).is_some() {
+            return None;
+        }
+        let window = self.windows.get(addr)?;
+        let previous = self.windows.get(&self.previous_window)?;
+        let same_pid = window.pid.is_some() && window.pid == previous.pid;
+        let same_class = !window.class.is_empty() && window.class == previous.class;
+        let spawned = window.parent_pid.is_some() && window.parent_pid == previous.pid;
+        if !(same_pid || same_class || spawned) {
+            return None;
+        }
+        self.memory
+            .get(&self.memory_key(&self.previous_window))
+            .copied()
+    }
+
+    // apply the layout of the workspace when it has no windows
+    fn focus_empty_workspace(&mut self) -> Vec<Action> {
+        let key = format!("workspace:{}", self.workspace().1);
+        let index = match self.memory.get(&key) {
+            Some(index) => *index,
+            None => match self.workspace_layout().or_else(|| self.monitor_layout()) {
+                Some(index) => {
+                    self.memory.insert(key, index);
+                    index
+                }
+                None => {
+                    log::debug!("No active window (empty workspace), maintaining current layout");
+                    return vec![];
+                }
+            },
+        };
+        self.switch_if_needed(index)
+    }
+
+    // user switched layout, remember it for the active window
+    fn layout_changed(&mut self, keyboard: String, layout: String) -> Vec<Action> {
+        if is_ignored(&self.options.ignore_keyboards, &keyboard) {
+            log::debug!("Skip ignored keyboard {}", keyboard);
+            return vec![];
+        }
+        log::debug!("Catch layout changed event on {} with {}", keyboard, layout);
+        self.add_keyboard(keyboard);
+        self.add_layout(layout.clone());
+        let index = match self.layout_index(&layout) {
+            Some(index) => index,
+            None => return vec![],
+        };
+        if self.active_layout == index {
+            log::debug!("Layout {} is current", layout);
+            return vec![];
+        }
+        self.active_layout = index;
+        self.note_class_layout(index);
+        let addr = self.active_window.clone();
+        let key = self.memory_key(&addr);
+        log::debug!("Saved layout {} with index {} on {}", layout, index, key);
+        self.memory.insert(key, index);
+        // the user's choice replaces the layout a title rule would restore
+        self.title_overrides.remove(&addr);
+        if self.options.track_titles {
+            if let Some(info) = self.windows.get(&addr) {
+                self.title_memory
+                    .entry(addr.clone())
+                    .or_default()
+                    .insert(info.title.clone(), index);
+            }
+        }
+        self.learn_layout(&addr, index)
+    }
+
+    // a browser tab switched to another site
+    // use the layout chosen for this title before, then a title rule,
+    // then the layout the window had before a title rule was applied
+    fn title_changed(&mut self, addr: &str, title: String) -> Vec<Action> {
+        let info = match self.windows.get_mut(addr) {
+            Some(info) if info.title != title => info,
+            _ => return vec![],
+        };
+        info.title = title;
+        if !self.options.track_titles {
+            return vec![];
+        }
+        let info = &self.windows[addr];
+        let remembered = self
+            .title_memory
+            .get(addr)
+            .and_then(|titles| titles.get(&info.title))
+            .copied();
+        let rule_layout = self
+            .options
+            .rules
+            .iter()
+            .filter(|rule| rule.title.is_some())
+            .find(|rule| rule.matches(info))
+            .and_then(|rule| rule.layout.index());
+        let key = self.memory_key(addr);
+        let index = match (remembered, rule_layout) {
+            (Some(index), _) => index,
+            (None, Some(index)) => {
+                if let Some(current) = self.memory.get(&key) {
+                    self.title_overrides
+                        .entry(addr.to_string())
+                        .or_insert(*current);
+                }
+                index
+            }
+            // no rule, keep the layout of the window
+            (None, None) => match self.title_overrides.remove(addr) {
+                Some(previous) => previous,
+                None => return vec![],
+            },
+        };
+        log::debug!("Title of {} changed, layout {}", addr, index);
+        self.memory.insert(key, index);
+        if addr == self.active_window {
+            self.switch_if_needed(index)
+        } else {
+            vec![]
+        }
+    }
+
+    // only change layout if it's different from current
+    fn switch_if_needed(&mut self, index: u16) -> Vec<Action> {
+        self.note_class_layout(index);
+        if self.active_layout == index {
+            log::debug!("Layout {} already active, skipping change", index);
+            return vec![];
+        }
+        if self.keyboards.is_empty() {
+            log::debug!("layout change interrupt: no keyboard added");
+            return vec![];
+        }
+        log::debug!("layout change {}", index);
+        self.active_layout = index;
+        let keyboards: Vec<(String, u16)> = self
+            .keyboards
+            .iter()
+            .filter_map(|kb| Some((kb.clone(), self.keyboard_index(kb, index)?)))
+            .collect();
+        if keyboards.is_empty() {
+            log::debug!("No keyboard has layout {}", index);
+            return vec![];
+        }
+        vec![Action::SwitchLayout { keyboards, index }]
+    }
+
+    // index of the layout in the keyboard own layouts
+    // None if the keyboard doesn't have it
+    fn keyboard_index(&self, keyboard: &str, index: u16) -> Option<u16> {
+        let own = match self.keyboard_layouts.get(keyboard) {
+            Some(own) => own,
+            None => return Some(index),
+        };
+        // layouts are unknown with kb_file option
+        let name = match self.kb_layouts.names.get(index as usize) {
+            Some(name) => name,
+            None => return Some(index),
+        };
+        let position = own.iter().position(|l| l == name);
+        if position.is_none() {
+            log::debug!("Skip keyboard {} without layout {}", keyboard, name);
+        }
+        position.map(|i| i as u16)
+    }
+
+    // long name of the active layout, the reverse of layout_index
+    fn active_long_name(&self) -> Option<String> {
+        let index = self.active_layout as usize;
+        match self.kb_layouts.long_names.get(index) {
+            Some(name) if !name.is_empty() => Some(name.clone()),
+            _ => self.layouts.get(index).cloned(),
+        }
+    }
+
+    // index of a layout by long name from an 'activelayout' event
+    // a keyboard with its own layouts may report a layout seen in another order
+    fn layout_index(&self, long_name: &str) -> Option<u16> {
+        self.kb_layouts
+            .long_names
+            .iter()
+            .position(|l| l == long_name)
+            .or_else(|| self.layouts.iter().position(|l| l == long_name))
+            .map(|i| i as u16)
+    }
+
+    // find first matching rule for the window
+    fn default_layout(&self, addr: &str) -> Option<u16> {
+        find_layout(&self.options.rules, self.windows.get(addr)?)
+    }
+
+    // default layout for the active workspace from [workspace_layouts]
+    fn workspace_layout(&self) -> Option<u16> {
+        let rule = self.options.workspace_layouts.iter().find(|rule| {
+            let (id, name) = self.workspace();
+            rule.matches(id, name)
+        })?;
+        rule.layout.index()
+    }
+
+    // default layout for the focused monitor from [monitor_layouts]
+    fn monitor_layout(&self) -> Option<u16> {
+        let description = self
+            .monitors
+            .get(&self.active_monitor)
+            .map(|d| d.as_str())
+            .unwrap_or("");
+        let rule = self
+            .options
+            .monitor_layouts
+            .iter()
+            .find(|rule| rule.matches(&self.active_monitor, description))?;
+        rule.layout.index()
+    }
+
+    // key in memory for the window according to memory_mode
+    fn memory_key(&self, addr: &str) -> String {
+        match self.options.memory_mode {
+            MemoryMode::Window => addr.to_string(),
+            MemoryMode::Class => match self.windows.get(addr) {
+                Some(info) if !info.class.is_empty() => format!("class:{}", info.class),
+                // unknown class, fallback to the window itself
+                _ => addr.to_string(),
+            },
+            MemoryMode::Workspace => format!("workspace:{}", self.workspace().1),
+            MemoryMode::Global => "global".to_string(),
+        }
+    }
+
+    // class and initial title (if enabled in options) of a known window
+    fn window_key(&self, addr: &str) -> Option<(String, Option<String>)> {
+        let info = self.windows.get(addr)?;
+        if info.class.is_empty() {
+            return None;
+        }
+        let title = if self.options.persist_by_title {
+            Some(info.initial_title.clone())
+        } else {
+            None
+        };
+        Some((info.class.clone(), title))
+    }
+
+    // find layout learned in previous sessions for this window
+    fn learned_layout(&self, addr: &str) -> Option<u16> {
+        if !self.options.persist {
+            return None;
+        }
+        let (class, title) = self.window_key(addr)?;
+        self.learned.as_ref()?.get(&class, title.as_deref())
+    }
+
+    // remember layout for the window class
+    fn learn_layout(&mut self, addr: &str, index: u16) -> Vec<Action> {
+        if !self.options.persist {
+            return vec![];
+        }
+        let (class, title) = match self.window_key(addr) {
+            Some(key) => key,
+            None => return vec![],
+        };
+        let changed = match self.learned.as_mut() {
+            Some(learned) => learned.set(&class, title.as_deref(), index),
+            None => false,
+        };
+        if !changed {
+            return vec![];
+        }
+        log::debug!("Learned layout {} for class {}", index, class);
+        vec![Action::SaveLearned]
+    }
+
+    // windows of the class or the window with the address
+    // the active window if nothing given
+    fn target_windows(
+        &self,
+        address: Option<&str>,
+        class: Option<&str>,
+    ) -> Result<Vec<String>, String> {
+        if let Some(class) = class {
+            return Ok(self
+                .windows
+                .iter()
+                .filter(|(_, info)| info.class == class)
+                .map(|(addr, _)| addr.clone())
+                .collect());
+        }
+        let addr = address.unwrap_or(&self.active_window);
+        if addr.is_empty() {
+            return Err("no active window".to_string());
+        }
+        Ok(vec![addr.to_string()])
+    }
+
+    /// set layout for a window or all windows of a class
+    /// layout for a class is remembered for windows opened later too
+    pub fn set_layout(
+        &mut self,
+        address: Option<&str>,
+        class: Option<&str>,
+        index: u16,
+    ) -> Result<Vec<Action>, String> {
+        let targets = self.target_windows(address, class)?;
+        for addr in targets.iter() {
+            let key = self.memory_key(addr);
+            self.memory.insert(key, index);
+        }
+        let mut actions = Vec::new();
+        match class {
+            Some(class) => {
+                self.class_pins.insert(class.to_string(), index);
+                if self.options.memory_mode == MemoryMode::Class {
+                    self.memory.insert(format!("class:{class}"), index);
+                }
+                if let Some(learned) = self.learned.as_mut() {
+                    if learned.set(class, None, index) {
+                        actions.push(Action::SaveLearned);
+                    }
+                }
+            }
+            None => {
+                for addr in targets.iter() {
+                    for action in self.learn_layout(addr, index) {
+                        if !actions.contains(&action) {
+                            actions.push(action);
+                        }
+                    }
+                }
+            }
+        }
+        if targets.contains(&self.active_window) {
+            actions.extend(self.switch_if_needed(index));
+        }
+        Ok(actions)
+    }
+
+    /// forget layout of a window or all windows of a class
+    pub fn forget(
+        &mut self,
+        address: Option<&str>,
+        class: Option<&str>,
+    ) -> Result<Vec<Action>, String> {
+        let targets = self.target_windows(address, class)?;
+        for addr in targets.iter() {
+            let key = self.memory_key(addr);
+            self.memory.remove(&key);
+        }
+        let class = match class {
+            Some(class) => class,
+            None => return Ok(vec![]),
+        };
+        self.memory.remove(&format!("class:{class}"));
+        self.class_pins.remove(class);
+        let changed = match self.learned.as_mut() {
+            Some(learned) => learned.forget(class),
+            None => false,
+        };
+        Ok(if changed {
+            vec![Action::SaveLearned]
+        } else {
+            vec![]
+        })
+    }
+
+    /// options with resolved layout names
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    /// make new options current, resolve layout names in them
+    /// learned layouts are not touched
+    pub fn set_options(&mut self, mut options: Options, kb_layouts: KbLayouts) {
+        options.resolve_layouts(&kb_layouts, &self.layouts);
+        let keyboards = options.keyboards.clone();
+        // the active layout was found before the layouts of Hyprland were known
+        let active = self.active_long_name();
+        self.kb_layouts = kb_layouts;
+        self.options = options;
+        if let Some(index) = active.and_then(|name| self.layout_index(&name)) {
+            self.active_layout = index;
+        }
+        // keyboards ignored by the new options
+        let ignore = &self.options.ignore_keyboards;
+        self.keyboards.retain(|kb| !is_ignored(ignore, kb));
+        // configured keyboards are kept even if Hyprland doesn't list them
+        self.discovered_keyboards
+            .retain(|kb| !keyboards.contains(kb));
+        for keyboard in keyboards {
+            self.add_keyboard(keyboard);
+        }
+    }
+
+    /// layouts seen in 'activelayout' events
+    pub fn seen_layouts(&self) -> &[String] {
+        &self.layouts
+    }
+
+    /// find layout index by number or name
+    pub fn resolve_layout(&self, layout: &LayoutRef) -> Result<u16, String> {
+        self.kb_layouts.resolve(layout, &self.layouts)
+    }
+
+    /// learned layouts, None if persistence is disabled
+    pub fn learned(&self) -> Option<&SavedState> {
+        self.learned.as_ref()
+    }
+
+    /// enable persistence with layouts learned in previous sessions
+    pub fn set_learned(&mut self, learned: SavedState) {
+        log::debug!("Loaded {} learned classes", learned.classes.len());
+        self.learned = Some(learned);
+    }
+
+    /// set current layout by its long name, ex: from 'active_keymap' of a keyboard
+    pub fn set_active_keymap(&mut self, long_name: &str) {
+        self.add_layout(long_name.to_string());
+        if let Some(index) = self.layout_index(long_name) {
+            self.active_layout = index;
+        }
+    }
+
+    // we have to fill this layouts list on go
+    fn add_layout(&mut self, long_name: String) {
+        if is_ignored(&self.options.ignore_layouts, &long_name) {
+            log::debug!("Layout ignored: {}", long_name);
+            return;
+        }
+        if !self.layouts.contains(&long_name) {
+            log::debug!("Layout stored: {}", long_name);
+            self.layouts.push(long_name);
+        }
+    }
+
+    fn add_keyboard(&mut self, name: String) {
+        if is_ignored(&self.options.ignore_keyboards, &name) {
+            log::debug!("Keyboard ignored: {}", name);
+            return;
+        }
+        if !self.keyboards.contains(&name) {
+            log::debug!("Keyboard added: {}", name);
+            self.keyboards.push(name);
+        }
+    }
+
+    /// active window, layouts and keyboards
+    pub fn status_json(&self) -> Value {
+        json!({
+            "active_window": self.active_window,
+            "active_class": self.active_class,
+            "active_workspace": self.active_workspace,
+            "active_workspace_id": self.active_workspace_id,
+            "active_special": self.active_special,
+            "active_monitor": self.active_monitor,
+            "active_layout": self.active_layout,
+            "memory_mode": self.options.memory_mode.as_str(),
+            "kb_layouts": self.kb_layouts.names,
+            "layouts": self.layouts,
+            "keyboards": self.keyboards,
+        })
+    }
+
+    /// known windows and their layouts
+    pub fn windows_json(&self) -> Value {
+        let mut list: Vec<Value> = self
+            .windows
+            .iter()
+            .map(|(addr, info)| {
+                json!({
+                    "address": addr,
+                    "class": info.class,
+                    "title": info.title,
+                    "initial_class": info.initial_class,
+                    "initial_title": info.initial_title,
+                    "pid": info.pid,
+                    "process": info.process,
+                    "layout": self.memory.get(&self.memory_key(addr)),
+                })
+            })
+            .collect();
+        list.sort_by(|a, b| a["address"].as_str().cmp(&b["address"].as_str()));
+        Value::Array(list)
+    }
+
+    /// everything we know, for debugging
+    pub fn dump_json(&self) -> Value {
+        json!({
+            "status": self.status_json(),
+            "windows": self.windows_json(),
+            "memory": self.memory,
+            "pins": self.class_pins,
+            "learned": self.learned,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // state with two layouts and one keyboard, "English (US)" is active
+    fn state(options: &str) -> State {
+        let mut state = State::new();
+        let options = crate::options::parse_options(options).unwrap();
+        let descriptions = [("us", "English (US)"), ("de", "German")]
+            .map(|(name, long_name)| (name.to_string(), long_name.to_string()))
+            .into();
+        state.set_options(options, KbLayouts::new("us,de", "", &descriptions));
+        state.set_active_keymap("English (US)");
+        state.add_layout("German".to_string());
+        state.add_keyboard("kb1".to_string());
+        state
+    }
+
+    fn open(state: &mut State, addr: &str, class: &str) -> Vec<Action> {
+        let mut actions = Vec::new();
+        for (name, data) in [
+            ("openwindow", format!("{addr},1,{class},title")),
+            ("activewindow", format!("{class},title")),
+            ("activewindowv2", addr.to_string()),
+        ] {
+            actions.extend(state.handle(HyprEvent::parse(name, &data).into()));
+        }
+        actions
+    }
+
+    fn focus(state: &mut State, addr: &str) -> Vec<Action> {
+        state.handle(HyprEvent::parse("activewindowv2", addr).into())
+    }
+
+    fn switch(index: u16) -> Vec<Action> {
+        vec![Action::SwitchLayout {
+            keyboards: vec![("kb1".to_string(), index)],
+            index,
+        }]
+    }
+
+    fn user_switch(state: &mut State, layout: &str) -> Vec<Action> {
+        state.handle(HyprEvent::parse("activelayout", &format!("kb1,{layout}")).into())
+    }
+
+    #[test]
+    fn window_layout_is_restored_on_focus() {
+        let mut state = state("");
+        assert_eq!(open(&mut state, "a1", "kitty"), vec![]);
+        assert_eq!(user_switch(&mut state, "German"), vec![]);
+        assert_eq!(open(&mut state, "b2", "kitty"), switch(0));
+        assert_eq!(focus(&mut state, "a1"), switch(1));
+        assert_eq!(focus(&mut state, "a1"), vec![]);
+    }
+
+    #[test]
+    fn rules_apply_to_new_windows() {
+        let mut state = state("[[default_layouts]]\n1 = [\"firefox\"]");
+        assert_eq!(open(&mut state, "a1", "firefox"), switch(1));
+        assert_eq!(open(&mut state, "b2", "kitty"), switch(0));
+    }
+
+    #[test]
+    fn class_mode_shares_layout() {
+        let mut state = state("memory_mode = \"class\"");
+        open(&mut state, "a1", "kitty");
+        user_switch(&mut state, "German");
+        open(&mut state, "b2", "firefox");
+        assert_eq!(open(&mut state, "c3", "kitty"), switch(1));
+    }
+
+    #[test]
+    fn closed_window_is_forgotten() {
+        let mut state = state("persist = false");
+        open(&mut state, "a1", "kitty");
+        user_switch(&mut state, "German");
+        state.handle(HyprEvent::parse("closewindow", "a1").into());
+        open(&mut state, "b2", "firefox");
+        // the same address reused by a new window
+        assert_eq!(
+            focus(&mut state, "a1"),
+            vec![Action::FetchWindow("0xa1".to_string())]
+        );
+    }
+
+    #[test]
+    fn learned_layout_is_saved_and_used() {
+        let mut state = state("persist = true");
+        state.set_learned(SavedState::default());
+        open(&mut state, "a1", "kitty");
+        assert_eq!(user_switch(&mut state, "German"), vec![Action::SaveLearned]);
+        open(&mut state, "b2", "firefox");
+        assert_eq!(open(&mut state, "c3", "kitty"), switch(1));
+    }
+
+    #[test]
+    fn rules_win_over_learned_layout() {
+        let mut state = state("persist = true\n\n[[default_layouts]]\n1 = [\"tg\"]");
+        state.set_learned(SavedState::default());
+        open(&mut state, "a1", "tg");
+        user_switch(&mut state, "English (US)");
+        open(&mut state, "b2", "kitty");
+        assert_eq!(open(&mut state, "c3", "tg"), switch(1));
+    }
+
+    #[test]
+    fn learned_layout_is_not_used_by_default() {
+        let mut state = state("");
+        state.set_learned(SavedState::default());
+        open(&mut state, "a1", "kitty");
+        assert_eq!(user_switch(&mut state, "German"), vec![]);
+        assert_eq!(open(&mut state, "b2", "kitty"), switch(0));
+    }
+
+    #[test]
+    fn class_pin_is_used_for_new_windows() {
+        let mut state = state("");
+        open(&mut state, "a1", "kitty");
+        assert_eq!(state.set_layout(None, Some("kitty"), 1), Ok(switch(1)));
+        // switching in a window of the class doesn't change the pin
+        user_switch(&mut state, "English (US)");
+        open(&mut state, "b2", "firefox");
+        assert_eq!(open(&mut state, "c3", "kitty"), switch(1));
+        assert_eq!(state.forget(None, Some("kitty")), Ok(vec![]));
+        open(&mut state, "d4", "firefox");
+        assert_eq!(open(&mut state, "e5", "kitty"), vec![]);
+    }
+
+    #[test]
+    fn unknown_window_is_fetched() {
+        let mut state = state("[[rules]]\ninitial_title = \"^Private\"\nlayout = 1");
+        state.handle(HyprEvent::parse("activewindow", "firefox,Example").into());
+        assert_eq!(
+            focus(&mut state, "a1"),
+            vec![Action::FetchWindow("0xa1".to_string())]
+        );
+        let info = WindowInfo {
+            class: "firefox".to_string(),
+            title: "Example".to_string(),
+            initial_class: "firefox".to_string(),
+            initial_title: "Private Browsing".to_string(),
+            ..Default::default()
+        };
+        let fetched = Event::WindowFetched {
+            addr: "0xa1".to_string(),
+            info: Some(info),
+        };
+        assert_eq!(state.handle(fetched), switch(1));
+    }
+
+    fn workspace(state: &mut State, id: i64, name: &str) {
+        state.handle(HyprEvent::parse("workspace", name).into());
+        state.handle(HyprEvent::parse("workspacev2", &format!("{id},{name}")).into());
+    }
+
+    #[test]
+    fn workspace_layouts_apply_after_rules() {
+        let options = "[[default_layouts]]\n0 = [\"kitty\"]\n\n[workspace_layouts]\n\"name:chat\" = \"de\"\n3 = 1";
+        let mut state = state(options);
+        workspace(&mut state, 5, "chat");
+        assert_eq!(open(&mut state, "a1", "firefox"), switch(1));
+        assert_eq!(open(&mut state, "b2", "kitty"), switch(0));
+        workspace(&mut state, 3, "3");
+        assert_eq!(open(&mut state, "c3", "firefox"), switch(1));
+        workspace(&mut state, 1, "1");
+        assert_eq!(open(&mut state, "d4", "firefox"), switch(0));
+    }
+
+    #[test]
+    fn workspace_mode_remembers_layout_per_workspace() {
+        let mut state =
+            state("memory_mode = \"workspace\"\n\n[[default_layouts]]\n0 = [\"kitty\"]");
+        workspace(&mut state, 1, "1");
+        open(&mut state, "a1", "firefox");
+        user_switch(&mut state, "German");
+        workspace(&mut state, 2, "2");
+        assert_eq!(open(&mut state, "b2", "firefox"), switch(0));
+        workspace(&mut state, 1, "1");
+        assert_eq!(open(&mut state, "c3", "firefox"), switch(1));
+        // rules win over the workspace layout
+        assert_eq!(open(&mut state, "d4", "kitty"), switch(0));
+        // empty workspace gets its layout too
+        workspace(&mut state, 2, "2");
+        focus(&mut state, "d4");
+        assert_eq!(
+            state.handle(HyprEvent::parse("activewindowv2", "").into()),
+            vec![]
+        );
+        workspace(&mut state, 1, "1");
+        assert_eq!(
+            state.handle(HyprEvent::parse("activewindowv2", "").into()),
+            switch(1)
+        );
+    }
+
+    #[test]
+    fn monitor_layouts_apply_after_workspace_layouts() {
+        let options = "[workspace_layouts]\n\"name:chat\" = 0\n\n[monitor_layouts]\n\"desc:Dell Inc. DELL U2720Q\" = \"de\"";
+        let mut state = state(options);
+        let monitor = |name: &str, description: &str, focused| MonitorInfo {
+            name: name.to_string(),
+            description: description.to_string(),
+            focused,
+        };
+        state.handle(Event::Monitors(vec![
+            monitor("eDP-1", "BOE 0x0BCA", true),
+            monitor("DP-1", "Dell Inc. DELL U2720Q 1234567", false),
+        ]));
+        assert_eq!(open(&mut state, "a1", "kitty"), vec![]);
+        state.handle(HyprEvent::parse("focusedmon", "DP-1,2").into());
+        state.handle(HyprEvent::parse("focusedmonv2", "DP-1,2").into());
+        assert_eq!(open(&mut state, "b2", "kitty"), switch(1));
+        assert_eq!(focus(&mut state, "a1"), switch(0));
+        focus(&mut state, "b2");
+        workspace(&mut state, 4, "chat");
+        assert_eq!(open(&mut state, "c3", "kitty"), switch(0));
+    }
+
+    fn special(state: &mut State, name: &str) -> Vec<Action> {
+        state.handle(HyprEvent::parse("activespecial", &format!("{name},DP-1")).into())
+    }
+
+    #[test]
+    fn special_workspace_layout_is_restored_on_close() {
+        let mut state = state("[workspace_layouts]\n\"special:term\" = \"us\"");
+        open(&mut state, "a1", "firefox");
+        user_switch(&mut state, "German");
+        assert_eq!(special(&mut state, "special:term"), vec![]);
+        assert_eq!(open(&mut state, "b2", "kitty"), switch(0));
+        assert_eq!(special(&mut state, ""), switch(1));
+        // focus returns to the window under it
+        assert_eq!(focus(&mut state, "a1"), vec![]);
+        // nothing under it, the previous layout is restored
+        state.handle(HyprEvent::parse("activewindowv2", "").into());
+        special(&mut state, "special:term");
+        assert_eq!(focus(&mut state, "b2"), switch(0));
+        assert_eq!(special(&mut state, ""), switch(1));
+        assert_eq!(state.class_layouts.get("kitty"), Some(&0));
+    }
+
+    #[test]
+    fn ignored_keyboards_and_layouts() {
+        let mut state = state("ignore_keyboards = [\"re:^scanner\"]\nignore_layouts = [\"Num*\"]");
+        open(&mut state, "a1", "kitty");
+        assert_eq!(user_switch(&mut state, "Numpad"), vec![]);
+        assert_eq!(state.layouts, ["English (US)", "German"]);
+        state.handle(HyprEvent::parse("activelayout", "scanner-1,German").into());
+        state.handle(HyprEvent::parse("activelayout", "yubikey,German").into());
+        assert_eq!(state.keyboards, ["kb1", "yubikey"]);
+        let actions = open(&mut state, "b2", "kitty");
+        assert_eq!(
+            actions,
+            vec![Action::SwitchLayout {
+                keyboards: vec![("kb1".to_string(), 0), ("yubikey".to_string(), 0)],
+                index: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn yubikey_and_wvkbd_are_ignored_by_default() {
+        let mut state = state("");
+        assert_eq!(user_switch(&mut state, "wvkbd"), vec![]);
+        state.handle(HyprEvent::parse("activelayout", "yubico-yubikey-otp,German").into());
+        assert_eq!(state.keyboards, ["kb1"]);
+        assert_eq!(state.layouts, ["English (US)", "German"]);
+    }
+
+    #[test]
+    fn keyboards_are_discovered() {
+        let mut state = state("");
+        let connected = |names: &[&str]| {
+            Event::Keyboards(names.iter().map(|n| KeyboardInfo::new(n, "", "")).collect())
+        };
+        state.handle(connected(&["kb1", "kb2", "kb3", "yubico-yubikey"]));
+        assert_eq!(state.keyboards, ["kb1", "kb2", "kb3"]);
+        state.handle(connected(&["kb3"]));
+        // kb1 was known before discovery
+        assert_eq!(state.keyboards, ["kb1", "kb3"]);
+        state.handle(connected(&["kb2"]));
+        assert_eq!(state.keyboards, ["kb1", "kb2"]);
+        assert_eq!(
+            state.handle(HyprEvent::parse("configreloaded", "").into()),
+            vec![Action::FetchKeyboards]
+        );
+    }
+
+    #[test]
+    fn configured_keyboards_are_kept_when_not_listed() {
+        let mut state = state("keyboards = [\"kb9\"]");
+        state.handle(Event::Keyboards(vec![KeyboardInfo::new("kb1", "", "")]));
+        assert_eq!(state.keyboards, ["kb9", "kb1"]);
+        state.handle(Event::Keyboards(vec![]));
+        assert_eq!(state.keyboards, ["kb9", "kb1"]);
+    }
+
+    #[test]
+    fn rejected_keyboard_is_not_discovered_again() {
+        let mut state = state("");
+        let connected = |names: &[&str]| {
+            Event::Keyboards(names.iter().map(|n| KeyboardInfo::new(n, "", "")).collect())
+        };
+        state.handle(connected(&["kb1", "kb2"]));
+        state.handle(Event::KeyboardRejected("kb2".to_string()));
+        state.handle(connected(&["kb1", "kb2"]));
+        assert_eq!(state.keyboards, ["kb1"]);
+        assert!(state.discovered_keyboards.is_empty());
+        // plugged again after it was unplugged
+        state.handle(connected(&["kb1"]));
+        state.handle(connected(&["kb1", "kb2"]));
+        assert_eq!(state.keyboards, ["kb1", "kb2"]);
+        assert_eq!(state.discovered_keyboards, ["kb2"]);
+    }
+
+    #[test]
+    fn keyboards_use_their_own_layouts() {
+        let mut state = state("");
+        state.handle(Event::Keyboards(vec![
+            KeyboardInfo::new("kb1", "us,de", ""),
+            KeyboardInfo::new("kb2", "de,us", ""),
+            KeyboardInfo::new("kb3", "de", ""),
+        ]));
+        open(&mut state, "a1", "kitty");
+        user_switch(&mut state, "German");
+        assert_eq!(
+            open(&mut state, "b2", "firefox"),
+            vec![Action::SwitchLayout {
+                keyboards: vec![("kb1".to_string(), 0), ("kb2".to_string(), 1)],
+                index: 0,
+            }]
+        );
+        assert_eq!(
+            focus(&mut state, "a1"),
+            vec![Action::SwitchLayout {
+                keyboards: vec![
+                    ("kb1".to_string(), 1),
+                    ("kb2".to_string(), 0),
+                    ("kb3".to_string(), 0)
+                ],
+                index: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn open_windows_are_synced_at_start() {
+        let mut state = state("[[default_layouts]]\n0 = [\"kitty\"]");
+        state.set_active_keymap("German");
+        let window = |class: &str| WindowInfo {
+            class: class.to_string(),
+            initial_class: class.to_string(),
+            ..Default::default()
+        };
+        let clients = Event::Clients {
+            windows: vec![
+                ("0xa1".to_string(), window("kitty")),
+                ("0xb2".to_string(), window("kitty")),
+            ],
+            focused: Some("0xb2".to_string()),
+        };
+        assert_eq!(state.handle(clients), vec![]);
+        assert_eq!(focus(&mut state, "a1"), switch(0));
+        assert_eq!(focus(&mut state, "b2"), switch(1));
+    }
+
+    #[test]
+    fn new_windows_inherit_layout_of_their_app() {
+        let mut state = state("inherit_layout = true");
+        let window = |class: &str, pid: i32, parent_pid: i32| WindowInfo {
+            class: class.to_string(),
+            initial_class: class.to_string(),
+            pid: Some(pid),
+            parent_pid: Some(parent_pid),
+            ..Default::default()
+        };
+        let fetched = |addr: &str, info: WindowInfo| Event::WindowFetched {
+            addr: addr.to_string(),
+            info: Some(info),
+        };
+        state.handle(Event::Clients {
+            windows: vec![("0xa1".to_string(), window("firefox", 100, 1))],
+            focused: Some("0xa1".to_string()),
+        });
+        user_switch(&mut state, "German");
+        // file dialog spawned by firefox
+        assert_eq!(
+            open(&mut state, "b2", "xdg-desktop-portal-gtk"),
+            vec![Action::FetchWindow("0xb2".to_string())]
+        );
+        let dialog = window("xdg-desktop-portal-gtk", 200, 100);
+        assert_eq!(state.handle(fetched("0xb2", dialog)), vec![]);
+        // unrelated app
+        open(&mut state, "c3", "kitty");
+        assert_eq!(
+            state.handle(fetched("0xc3", window("kitty", 300, 1))),
+            switch(0)
+        );
+        // another kitty window
+        open(&mut state, "d4", "kitty");
+        assert_eq!(
+            state.handle(fetched("0xd4", window("kitty", 301, 1))),
+            vec![]
+        );
+        assert_eq!(focus(&mut state, "b2"), switch(1));
+    }
+
+    #[test]
+    fn process_rules_follow_terminal_foreground_process() {
+        let mut state = state("[[rules]]\nprocess = \"^n?vim$\"\nlayout = \"us\"");
+        let kitty = WindowInfo {
+            class: "kitty".to_string(),
+            initial_class: "kitty".to_string(),
+            pid: Some(100),
+            process: "zsh".to_string(),
+            ..Default::default()
+        };
+        let running = |process: &str| Event::ProcessFetched {
+            addr: "0xa1".to_string(),
+            process: process.to_string(),
+        };
+        state.handle(Event::Clients {
+            windows: vec![("0xa1".to_string(), kitty)],
+            focused: Some("0xa1".to_string()),
+        });
+        user_switch(&mut state, "German");
+        assert_eq!(
+            state.handle(HyprEvent::parse("windowtitlev2", "a1,nvim").into()),
+            vec![Action::FetchProcess {
+                addr: "0xa1".to_string(),
+                pid: 100
+            }]
+        );
+        assert_eq!(state.handle(running("nvim")), switch(0));
+        assert_eq!(state.handle(running("nvim")), vec![]);
+        assert_eq!(state.handle(running("zsh")), switch(1));
+    }
+
+    #[test]
+    fn processes_are_looked_up_only_when_needed() {
+        assert_eq!(
+            state("").process_lookups(),
+            ProcessLookups {
+                parent_pid: false,
+                process: false
+            }
+        );
+        let mut state = state("[[rules]]\nprocess = \"^n?vim$\"\nlayout = \"us\"");
+        assert!(state.process_lookups().process);
+        let kitty = WindowInfo {
+            class: "kitty".to_string(),
+            pid: Some(100),
+            ..Default::default()
+        };
+        state.handle(Event::Clients {
+            windows: vec![("0xa1".to_string(), kitty)],
+            focused: Some("0xa1".to_string()),
+        });
+        open(&mut state, "b2", "firefox");
+        // titles of background windows don't trigger /proc reads
+        assert_eq!(
+            state.handle(HyprEvent::parse("windowtitlev2", "a1,nvim").into()),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn title_changes_apply_rules_and_remember_layouts() {
+        let mut state = state(
+            "track_titles = true\n\n[[rules]]\ntitle = \"^Google Translate\"\nlayout = \"de\"",
+        );
+        let title = |state: &mut State, title: &str| {
+            state.handle(HyprEvent::parse("windowtitlev2", &format!("a1,{title}")).into())
+        };
+        open(&mut state, "a1", "firefox");
+        assert_eq!(title(&mut state, "Google Translate - Firefox"), switch(1));
+        // the layout before the rule is restored
+        assert_eq!(title(&mut state, "GitHub - Firefox"), switch(0));
+        assert_eq!(title(&mut state, "Google Translate - Firefox"), switch(1));
+        // the layout chosen for a title wins over the rule
+        user_switch(&mut state, "English (US)");
+        assert_eq!(title(&mut state, "GitHub - Firefox"), vec![]);
+        user_switch(&mut state, "German");
+        assert_eq!(title(&mut state, "Google Translate - Firefox"), switch(0));
+        // the layout of the last title is restored on focus
+        open(&mut state, "b2", "kitty");
+        assert_eq!(focus(&mut state, "a1"), vec![]);
+        assert_eq!(title(&mut state, "GitHub - Firefox"), switch(1));
+    }
+
+    #[test]
+    fn layout_before_title_rule_is_restored() {
+        let mut state = state("track_titles = true\n\n[[rules]]\ntitle = \"^Slack\"\nlayout = 0");
+        let title = |state: &mut State, title: &str| {
+            state.handle(HyprEvent::parse("windowtitlev2", &format!("a1,{title}")).into())
+        };
+        open(&mut state, "a1", "chromium");
+        user_switch(&mut state, "German");
+        assert_eq!(title(&mut state, "Slack - general"), switch(0));
+        assert_eq!(title(&mut state, "Slack - random"), vec![]);
+        assert_eq!(title(&mut state, "Inbox"), switch(1));
+        assert_eq!(title(&mut state, "Docs"), vec![]);
+    }
+
+    #[test]
+    fn fallback_to_named_layout() {
+        let mut state = state("fallback = \"de\"");
+        assert_eq!(open(&mut state, "a1", "kitty"), switch(1));
+    }
+
+    #[test]
+    fn fallback_keeps_current_layout() {
+        let mut state = state("fallback = \"keep-current\"");
+        open(&mut state, "a1", "kitty");
+        user_switch(&mut state, "German");
+        assert_eq!(open(&mut state, "b2", "firefox"), vec![]);
+    }
+
+    #[test]
+    fn fallback_to_last_layout_of_class() {
+        let mut state = state("fallback = \"last-used-for-class\"\npersist = false");
+        open(&mut state, "a1", "kitty");
+        user_switch(&mut state, "German");
+        assert_eq!(open(&mut state, "b2", "firefox"), switch(0));
+        assert_eq!(open(&mut state, "c3", "kitty"), switch(1));
+        user_switch(&mut state, "English (US)");
+        open(&mut state, "d4", "firefox");
+        assert_eq!(open(&mut state, "e5", "kitty"), vec![]);
+    }
+
+    #[test]
+    fn rejected_keyboard_is_dropped() {
+        let mut state = state("keyboards = [\"kb2\"]");
+        state.handle(Event::KeyboardRejected("kb2".to_string()));
+        assert_eq!(state.keyboards, ["kb1"]);
+    }
+}
@@ -0,0 +1,100 @@
+//! read and write learned layouts between daemon restarts
+//! located at $XDG_STATE_HOME/hyprland-per-window-layout/state.json
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// layouts learned by window class, the content of state.json
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[non_exhaustive]
+pub struct SavedState {
+    #[serde(default)]
+    /// window class => layout index
+    pub classes: HashMap<String, u16>,
+    #[serde(default)]
+    /// window class => initial title => layout index
+    pub titles: HashMap<String, HashMap<String, u16>>,
+}
+
+impl SavedState {
+    /// find learned layout for a window, initial title is checked first if given
+    pub fn get(&self, class: &str, initial_title: Option<&str>) -> Option<u16> {
+        if let Some(title) = initial_title {
+            if let Some(index) = self.titles.get(class).and_then(|t| t.get(title)) {
+                return Some(*index);
+            }
+        }
+        self.classes.get(class).copied()
+    }
+
+    /// remember layout for a window class (and initial title if given)
+    /// returns true if something was changed
+    pub fn set(&mut self, class: &str, initial_title: Option<&str>, index: u16) -> bool {
+        let mut changed = self.classes.insert(class.to_string(), index) != Some(index);
+        if let Some(title) = initial_title {
+            let titles = self.titles.entry(class.to_string()).or_default();
+            changed |= titles.insert(title.to_string(), index) != Some(index);
+        }
+        changed
+    }
+
+    /// forget everything learned for a window class
+    /// returns true if something was changed
+    pub fn forget(&mut self, class: &str) -> bool {
+        let removed_class = self.classes.remove(class).is_some();
+        let removed_titles = self.titles.remove(class).is_some();
+        removed_class || removed_titles
+    }
+}
+
+// path to the state file
+// in $HOME/.local/state/hyprland-per-window-layout/state.json
+fn state_file_path() -> Option<PathBuf> {
+    dirs::state_dir().map(|dir| dir.join("hyprland-per-window-layout").join("state.json"))
+}
+
+/// read the state file, ignore if it doesn't exist
+pub fn load_state() -> SavedState {
+    let path = match state_file_path() {
+        Some(path) => path,
+        None => return SavedState::default(),
+    };
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => {
+            log::debug!("State file not found: {}", path.display());
+            return SavedState::default();
+        }
+    };
+    match serde_json::from_str(&content) {
+        Ok(state) => state,
+        Err(e) => {
+            log::warn!("Error parsing state file {}: {}", path.display(), e);
+            SavedState::default()
+        }
+    }
+}
+
+/// write the state file atomically: write a temporary file and rename it
+pub fn save_state(state: &SavedState) -> std::io::Result<()> {
+    let path = match state_file_path() {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let tmp_path = path.with_extension("json.tmp");
+    let content = serde_json::to_string_pretty(state)?;
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(content.as_bytes())?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, &path)?;
+    log::debug!("State saved to {}", path.display());
+    Ok(())
+}
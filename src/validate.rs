@@ -0,0 +1,536 @@
+//! strict validation of options.toml
+//! every problem is reported with its line and column
+
+use regex::Regex;
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use toml::Spanned;
+
+use crate::layouts::{KbLayouts, LayoutRef};
+use crate::options::MemoryMode;
+use crate::rules::NamePattern;
+
+/// how bad a problem is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Severity {
+    /// the option is ignored
+    Error,
+    /// the option works, but probably not as intended
+    Warning,
+}
+
+/// a problem found in options.toml
+#[derive(Debug)]
+pub struct Problem {
+    /// error or warning
+    pub severity: Severity,
+    /// starts from 1
+    pub line: usize,
+    /// starts from 1
+    pub column: usize,
+    /// what is wrong, ex: unknown option 'color'
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line, self.column, severity, self.message
+        )
+    }
+}
+
+// toml value that remembers where it was found in the file
+#[derive(Debug)]
+enum Node {
+    String(String),
+    Integer(i64),
+    Float,
+    Boolean,
+    Array(Vec<Spanned<Node>>),
+    Table(Vec<(Spanned<String>, Spanned<Node>)>),
+}
+
+impl Node {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Node::String(_) => "a string",
+            Node::Integer(_) => "an integer",
+            Node::Float => "a float",
+            Node::Boolean => "a boolean",
+            Node::Array(_) => "an array",
+            Node::Table(_) => "a table",
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NodeVisitor;
+
+        impl<'de> Visitor<'de> for NodeVisitor {
+            type Value = Node;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a toml value")
+            }
+            fn visit_str<E>(self, v: &str) -> Result<Node, E> {
+                Ok(Node::String(v.to_string()))
+            }
+            fn visit_string<E>(self, v: String) -> Result<Node, E> {
+                Ok(Node::String(v))
+            }
+            fn visit_i64<E>(self, v: i64) -> Result<Node, E> {
+                Ok(Node::Integer(v))
+            }
+            fn visit_u64<E>(self, v: u64) -> Result<Node, E> {
+                Ok(Node::Integer(v as i64))
+            }
+            fn visit_f64<E>(self, _v: f64) -> Result<Node, E> {
+                Ok(Node::Float)
+            }
+            fn visit_bool<E>(self, _v: bool) -> Result<Node, E> {
+                Ok(Node::Boolean)
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Node, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(Node::Array(items))
+            }
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Node, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = map.next_entry()? {
+                    items.push(item);
+                }
+                Ok(Node::Table(items))
+            }
+        }
+
+        deserializer.deserialize_any(NodeVisitor)
+    }
+}
+
+struct Checker<'a> {
+    content: &'a str,
+    kb: &'a KbLayouts,
+    seen: &'a [String],
+    problems: Vec<Problem>,
+}
+
+impl Checker<'_> {
+    fn report(&mut self, severity: Severity, span: Range<usize>, message: String) {
+        let (line, column) = line_column(self.content, span.start);
+        self.problems.push(Problem {
+            severity,
+            line,
+            column,
+            message,
+        });
+    }
+
+    fn error(&mut self, span: Range<usize>, message: String) {
+        self.report(Severity::Error, span, message);
+    }
+
+    fn warning(&mut self, span: Range<usize>, message: String) {
+        self.report(Severity::Warning, span, message);
+    }
+
+    // report wrong type, returns true if the type is expected
+    fn expect(&mut self, key: &str, node: &Spanned<Node>, expected: &str) -> bool {
+        let found = node.get_ref().type_name();
+        if found == expected {
+            return true;
+        }
+        self.error(
+            node.span(),
+            format!("{key} should be {expected}, found {found}"),
+        );
+        false
+    }
+
+    fn check_root(&mut self, items: &[(Spanned<String>, Spanned<Node>)]) {
+        for (key, value) in items {
+            match key.get_ref().as_str() {
+                "keyboards" => self.check_string_list("keyboards", value),
+                "ignore_keyboards" | "ignore_layouts" => self.check_patterns(key.get_ref(), value),
+                "persist" | "persist_by_title" | "inherit_layout" | "track_titles" => {
+                    self.expect(key.get_ref(), value, "a boolean");
+                }
+                "memory_mode" => self.check_memory_mode(value),
+                "fallback" => self.check_fallback(value),
+                "default_layouts" => self.check_default_layouts(value),
+                "rules" => self.check_rules(value),
+                "workspace_layouts" => self.check_layout_table("workspace_layouts", value),
+                "monitor_layouts" => self.check_layout_table("monitor_layouts", value),
+                name => self.error(key.span(), format!("unknown option '{name}'")),
+            }
+        }
+    }
+
+    fn check_string_list(&mut self, key: &str, node: &Spanned<Node>) {
+        if let Node::Array(items) = node.get_ref() {
+            for item in items {
+                self.expect(&format!("item of {key}"), item, "a string");
+            }
+        } else {
+            self.expect(key, node, "an array");
+        }
+    }
+
+    // globs or regexes with "re:" prefix
+    fn check_patterns(&mut self, key: &str, node: &Spanned<Node>) {
+        self.check_string_list(key, node);
+        if let Node::Array(items) = node.get_ref() {
+            for item in items {
+                if let Node::String(pattern) = item.get_ref() {
+                    if let Err(e) = NamePattern::parse(pattern) {
+                        self.error(item.span(), format!("bad pattern in {key}: {e}"));
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_memory_mode(&mut self, node: &Spanned<Node>) {
+        if let Node::String(value) = node.get_ref() {
+            if MemoryMode::parse(value).is_none() {
+                self.error(
+                    node.span(),
+                    format!(
+                        "unknown memory_mode '{value}', expected one of: window, class, workspace, global"
+                    ),
+                );
+            }
+        } else {
+            self.expect("memory_mode", node, "a string");
+        }
+    }
+
+    // a policy or a layout
+    fn check_fallback(&mut self, node: &Spanned<Node>) {
+        match node.get_ref() {
+            Node::String(value)
+                if ["first", "keep-current", "last-used-for-class"].contains(&value.as_str()) => {}
+            Node::String(_) | Node::Integer(_) => self.check_layout_value(node),
+            _ => {
+                self.expect("fallback", node, "a string or an integer");
+            }
+        }
+    }
+
+    // check layout index is in range and layout name exists
+    fn check_layout(&mut self, span: Range<usize>, layout: &LayoutRef) {
+        match layout {
+            LayoutRef::Index(index) => {
+                let count = self.kb.names.len();
+                if count > 0 && *index as usize >= count {
+                    self.warning(
+                        span,
+                        format!(
+                            "layout index {index} is out of range, {count} layouts are configured in Hyprland"
+                        ),
+                    );
+                }
+            }
+            LayoutRef::Name(_) => {
+                // names can't be checked without Hyprland
+                if self.kb.names.is_empty() && self.seen.is_empty() {
+                    return;
+                }
+                if let Err(e) = self.kb.resolve(layout, self.seen) {
+                    self.error(span, e);
+                }
+            }
+        }
+    }
+
+    // [[default_layouts]]
+    // 1 = ["org.telegram.desktop"]
+    fn check_default_layouts(&mut self, node: &Spanned<Node>) {
+        let tables: Vec<&Spanned<Node>> = match node.get_ref() {
+            Node::Array(items) => items.iter().collect(),
+            Node::Table(_) => vec![node],
+            _ => {
+                self.expect("default_layouts", node, "an array");
+                return;
+            }
+        };
+        // class => layout it was first listed under
+        let mut classes: HashMap<String, String> = HashMap::new();
+        for table in tables {
+            let items = match table.get_ref() {
+                Node::Table(items) => items,
+                _ => {
+                    self.expect("default_layouts", table, "a table");
+                    continue;
+                }
+            };
+            for (key, value) in items {
+                self.check_layout(key.span(), &LayoutRef::from_key(key.get_ref()));
+                let list = match value.get_ref() {
+                    Node::Array(list) => list,
+                    _ => {
+                        self.expect(
+                            &format!("default_layouts.{}", key.get_ref()),
+                            value,
+                            "an array",
+                        );
+                        continue;
+                    }
+                };
+                for class in list {
+                    let name = match class.get_ref() {
+                        Node::String(name) => name,
+                        _ => {
+                            self.expect("window class", class, "a string");
+                            continue;
+                        }
+                    };
+                    match classes.get(name) {
+                        Some(first) if first == key.get_ref() => self.warning(
+                            class.span(),
+                            format!("class '{name}' is listed twice under {first}"),
+                        ),
+                        Some(first) => self.warning(
+                            class.span(),
+                            format!(
+                                "class '{name}' is listed under both {first} and {}",
+                                key.get_ref()
+                            ),
+                        ),
+                        None => {
+                            classes.insert(name.clone(), key.get_ref().clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // [[rules]]
+    // class = "^jetbrains-.*"
+    // layout = 1
+    fn check_rules(&mut self, node: &Spanned<Node>) {
+        let rules = match node.get_ref() {
+            Node::Array(items) => items,
+            _ => {
+                self.expect("rules", node, "an array");
+                return;
+            }
+        };
+        for rule in rules {
+            let items = match rule.get_ref() {
+                Node::Table(items) => items,
+                _ => {
+                    self.expect("rule", rule, "a table");
+                    continue;
+                }
+            };
+            let mut has_layout = false;
+            let mut has_pattern = false;
+            for (key, value) in items {
+                match key.get_ref().as_str() {
+                    "class" | "initial_class" | "title" | "initial_title" | "process" => {
+                        has_pattern = true;
+                        if let Node::String(re) = value.get_ref() {
+                            if let Err(e) = Regex::new(re) {
+                                self.error(
+                                    value.span(),
+                                    format!("bad regex in {}: {e}", key.get_ref()),
+                                );
+                            }
+                        } else {
+                            self.expect(key.get_ref(), value, "a string");
+                        }
+                    }
+                    "layout" => {
+                        has_layout = true;
+                        self.check_layout_value(value);
+                    }
+                    name => self.error(key.span(), format!("unknown rule option '{name}'")),
+                }
+            }
+            if !has_layout {
+                self.error(rule.span(), "rule has no layout".to_string());
+            }
+            if !has_pattern {
+                self.error(
+                    rule.span(),
+                    "rule should have class, initial_class, title, initial_title or process"
+                        .to_string(),
+                );
+            }
+        }
+    }
+
+    // [workspace_layouts]
+    // "name:chat" = "ua"
+    // [monitor_layouts]
+    // "desc:Dell Inc. DELL U2720Q" = "de"
+    fn check_layout_table(&mut self, key: &str, node: &Spanned<Node>) {
+        let items = match node.get_ref() {
+            Node::Table(items) => items,
+            _ => {
+                self.expect(key, node, "a table");
+                return;
+            }
+        };
+        for (name, value) in items {
+            let name_ref = name.get_ref().as_str();
+            if name_ref.is_empty() || name_ref == "name:" || name_ref == "desc:" {
+                self.error(name.span(), format!("empty name in {key}"));
+            }
+            self.check_layout_value(value);
+        }
+    }
+
+    // layout = 1 or layout = "us"
+    fn check_layout_value(&mut self, node: &Spanned<Node>) {
+        match node.get_ref() {
+            Node::Integer(index) => match u16::try_from(*index) {
+                Ok(index) => self.check_layout(node.span(), &LayoutRef::Index(index)),
+                Err(_) => self.error(node.span(), format!("bad layout index {index}")),
+            },
+            Node::String(name) => self.check_layout(node.span(), &LayoutRef::from_key(name)),
+            found => self.error(
+                node.span(),
+                format!(
+                    "layout should be an integer or a string, found {}",
+                    found.type_name()
+                ),
+            ),
+        }
+    }
+}
+
+// 1-based line and column of a byte offset
+fn line_column(content: &str, offset: usize) -> (usize, usize) {
+    let before = &content[..offset.min(content.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let column = before[line_start..].chars().count() + 1;
+    (line, column)
+}
+
+/// find all problems in options.toml content
+/// kb and seen layouts are used to check layout indices and names
+pub fn check_options(content: &str, kb: &KbLayouts, seen: &[String]) -> Vec<Problem> {
+    let mut checker = Checker {
+        content,
+        kb,
+        seen,
+        problems: Vec::new(),
+    };
+    match toml::from_str::<Node>(content) {
+        Ok(Node::Table(items)) => checker.check_root(&items),
+        Ok(_) => checker.error(0..0, "options.toml should be a table".to_string()),
+        Err(e) => {
+            let span = e.span().unwrap_or(0..0);
+            checker.error(span, e.message().trim().to_string());
+        }
+    }
+    checker.problems
+}
+
+/// true if the options can't be used as they are
+pub fn has_errors(problems: &[Problem]) -> bool {
+    problems.iter().any(|p| p.severity == Severity::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(content: &str) -> Vec<String> {
+        let kb = KbLayouts {
+            names: vec!["us".to_string(), "de".to_string()],
+            long_names: vec!["English (US)".to_string(), "German".to_string()],
+        };
+        check_options(content, &kb, &[])
+            .iter()
+            .map(|problem| problem.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let content = "a = 1\nключ = \"значение\"";
+        assert_eq!(line_column(content, 0), (1, 1));
+        assert_eq!(line_column(content, 6), (2, 1));
+        // after "ключ = " which takes 11 bytes
+        assert_eq!(line_column(content, 17), (2, 8));
+        assert_eq!(line_column(content, 1000), (2, 18));
+    }
+
+    #[test]
+    fn problems_point_to_values() {
+        assert_eq!(
+            check("persist = true\nmemory_mode = \"tab\"\ncolor = 1"),
+            [
+                "2:15: error: unknown memory_mode 'tab', expected one of: window, class, workspace, global",
+                "3:1: error: unknown option 'color'",
+            ]
+        );
+    }
+
+    #[test]
+    fn classes_listed_twice_are_reported() {
+        let content = "[[default_layouts]]\n\
+                       1 = [\"tg\", \"tg\"]\n\
+                       0 = [\"tg\"]";
+        assert_eq!(
+            check(content),
+            [
+                "2:12: warning: class 'tg' is listed twice under 1",
+                "3:6: warning: class 'tg' is listed under both 1 and 0",
+            ]
+        );
+    }
+
+    #[test]
+    fn default_layouts_tables_are_merged() {
+        let content = "[[default_layouts]]\n\
+                       de = [\"tg\"]\n\
+                       [[default_layouts]]\n\
+                       us = [\"tg\"]\n\
+                       fr = [\"kitty\"]";
+        // classes are checked across all tables
+        assert_eq!(
+            check(content),
+            [
+                "4:7: warning: class 'tg' is listed under both de and us",
+                "5:1: error: unknown layout 'fr', available: us, de, English (US), German",
+            ]
+        );
+    }
+
+    #[test]
+    fn fallback_is_a_policy_or_a_layout() {
+        assert!(check("fallback = \"keep-current\"").is_empty());
+        assert!(check("fallback = \"de\"").is_empty());
+        assert_eq!(
+            check("fallback = 5"),
+            ["1:12: warning: layout index 5 is out of range, 2 layouts are configured in Hyprland"]
+        );
+        assert_eq!(
+            check("fallback = \"last\""),
+            ["1:12: error: unknown layout 'last', available: us, de, English (US), German"]
+        );
+        assert_eq!(
+            check("fallback = true"),
+            ["1:12: error: fallback should be a string or an integer, found a boolean"]
+        );
+    }
+}
@@ -0,0 +1,54 @@
+// watch options.toml and reload it when changed
+
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+use std::path::Path;
+
+use hyprland_per_window_layout::daemon::Daemon;
+
+// start watching the options file in a background thread
+// the directory is watched, because editors often replace the file
+pub fn watch_options(options_path: &Path, daemon: Daemon) {
+    let (dir, file_name) = match (options_path.parent(), options_path.file_name()) {
+        (Some(dir), Some(file_name)) => (dir.to_path_buf(), file_name.to_os_string()),
+        _ => return,
+    };
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        log::warn!("Failed to create {}: {}", dir.display(), e);
+        return;
+    }
+    let inotify = match Inotify::init(InitFlags::IN_CLOEXEC) {
+        Ok(inotify) => inotify,
+        Err(e) => {
+            log::warn!("Failed to watch options: {}", e);
+            return;
+        }
+    };
+    let flags = AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE;
+    if let Err(e) = inotify.add_watch(&dir, flags) {
+        log::warn!("Failed to watch {}: {}", dir.display(), e);
+        return;
+    }
+    log::debug!("Watching {}", options_path.display());
+    std::thread::spawn(move || loop {
+        let events = match inotify.read_events() {
+            Ok(events) => events,
+            Err(e) => {
+                log::warn!("Failed to read inotify events: {}", e);
+                return;
+            }
+        };
+        if events
+            .iter()
+            .any(|event| event.name.as_ref() == Some(&file_name))
+        {
+            log::debug!("options.toml changed");
+            if let Err(e) = daemon.reload_options() {
+                println!("{e}");
+                println!("Keep using previous options");
+            }
+        }
+    });
+}
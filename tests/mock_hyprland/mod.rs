@@ -0,0 +1,361 @@
+// fake Hyprland for end-to-end tests
+// serves .socket.sock (requests) and .socket2.sock (events) in a temporary XDG_RUNTIME_DIR
+// and runs the daemon against it
+
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+const SIGNATURE: &str = "mock";
+const TIMEOUT: Duration = Duration::from_secs(5);
+// no requests for this long means the daemon has handled all events
+const IDLE: Duration = Duration::from_millis(300);
+
+lazy_static::lazy_static! {
+    // tests wait until the daemon is idle, one daemon at a time keeps the timing stable
+    static ref DAEMON_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// replies of the request socket and requests received
+#[derive(Default)]
+struct Server {
+    replies: HashMap<String, String>, // request => reply
+    rejected: Vec<String>,            // keyboards that fail switchxkblayout
+    requests: Vec<String>,
+    last_request: Option<Instant>,
+}
+
+impl Server {
+    fn reply(&mut self, request: &str) -> String {
+        self.requests.push(request.to_string());
+        self.last_request = Some(Instant::now());
+        if let Some(commands) = request.strip_prefix("[[BATCH]]") {
+            return commands
+                .split(';')
+                .map(|command| self.reply_command(command))
+                .collect::<Vec<_>>()
+                .join("\n\n");
+        }
+        self.reply_command(request)
+    }
+
+    fn reply_command(&self, command: &str) -> String {
+        if let Some(reply) = self.replies.get(command) {
+            return reply.clone();
+        }
+        let rejected = command
+            .strip_prefix("switchxkblayout ")
+            .and_then(|args| args.split(' ').next())
+            .is_some_and(|kb| self.rejected.iter().any(|r| r == kb));
+        if rejected {
+            "device not found".to_string()
+        } else {
+            "ok".to_string()
+        }
+    }
+}
+
+pub struct MockHyprland {
+    root: PathBuf,
+    // name of the control socket, not shared with a running daemon of the user
+    instance: String,
+    server: Arc<Mutex<Server>>,
+    events: UnixListener,
+    stream: Option<UnixStream>,
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl MockHyprland {
+    // Hyprland with "us,de" layouts and one keyboard "kb1"
+    pub fn start(name: &str) -> MockHyprland {
+        let lock = DAEMON_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let root = std::env::temp_dir().join(format!("hpwl-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let socket_dir = root.join("runtime").join("hypr").join(SIGNATURE);
+        fs::create_dir_all(&socket_dir).unwrap();
+        // layout descriptions for XKB_CONFIG_ROOT
+        fs::create_dir_all(root.join("rules")).unwrap();
+        fs::write(
+            root.join("rules").join("evdev.lst"),
+            "! layout\n  us              English (US)\n  de              German\n",
+        )
+        .unwrap();
+
+        let mut server = Server::default();
+        for (request, reply) in [
+            ("j/getoption input:kb_layout", r#"{"str": "us,de"}"#),
+            ("j/getoption input:kb_variant", r#"{"str": ""}"#),
+            ("j/getoption input:kb_file", r#"{"str": "[[EMPTY]]"}"#),
+            (
+                "j/devices",
+                r#"{"keyboards": [{"name": "kb1", "active_keymap": "English (US)"}]}"#,
+            ),
+            ("j/clients", "[]"),
+            (
+                "j/monitors",
+                r#"[{"name": "eDP-1", "description": "BOE 0x0BCA", "focused": true}]"#,
+            ),
+        ] {
+            server
+                .replies
+                .insert(request.to_string(), reply.to_string());
+        }
+        let server = Arc::new(Mutex::new(server));
+        let events = serve(&socket_dir, server.clone());
+
+        MockHyprland {
+            root,
+            instance: format!(
+                "hyprland-per-window-layout-test-{}-{name}",
+                std::process::id()
+            ),
+            server,
+            events,
+            stream: None,
+            _lock: lock,
+        }
+    }
+
+    // override the reply for a request, ex: "j/clients"
+    pub fn set_reply(&self, request: &str, reply: &str) {
+        let mut server = self.server.lock().unwrap();
+        server
+            .replies
+            .insert(request.to_string(), reply.to_string());
+    }
+
+    // switchxkblayout fails for this keyboard as for an unplugged device
+    pub fn reject_keyboard(&self, keyboard: &str) {
+        self.server
+            .lock()
+            .unwrap()
+            .rejected
+            .push(keyboard.to_string());
+    }
+
+    pub fn write_options(&self, content: &str) {
+        let dir = self.config_dir();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("options.toml"), content).unwrap();
+    }
+
+    fn config_dir(&self) -> PathBuf {
+        self.root.join("config").join("hyprland-per-window-layout")
+    }
+
+    pub fn state_file(&self) -> PathBuf {
+        self.root
+            .join("state")
+            .join("hyprland-per-window-layout")
+            .join("state.json")
+    }
+
+    // run the daemon and wait until it listens to events
+    pub fn spawn_daemon(&mut self) -> Daemon {
+        let child = Command::new(env!("CARGO_BIN_EXE_hyprland-per-window-layout"))
+            .env_clear()
+            .env("PATH", std::env::var("PATH").unwrap_or_default())
+            .env("HOME", &self.root)
+            .env("HYPRLAND_INSTANCE_SIGNATURE", SIGNATURE)
+            .env("HYPRLAND_PER_WINDOW_LAYOUT_INSTANCE", &self.instance)
+            .env("XDG_RUNTIME_DIR", self.root.join("runtime"))
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .env("XDG_STATE_HOME", self.root.join("state"))
+            .env("XKB_CONFIG_ROOT", &self.root)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut daemon = Daemon { child: Some(child) };
+        self.accept(&mut daemon);
+        daemon
+    }
+
+    // Hyprland crashed and was started again with another signature
+    // sockets of the old instance stay on disk, but nobody listens to them
+    pub fn restart(&mut self, daemon: &mut Daemon, signature: &str) {
+        let socket_dir = self.root.join("runtime").join("hypr").join(signature);
+        fs::create_dir_all(&socket_dir).unwrap();
+        self.stream = None;
+        self.events = serve(&socket_dir, self.server.clone());
+        self.accept(daemon);
+    }
+
+    // close the event socket as a restarted Hyprland does
+    // and wait until the daemon connects again
+    pub fn reconnect(&mut self, daemon: &mut Daemon) {
+        self.stream = None;
+        self.accept(daemon);
+    }
+
+    fn accept(&mut self, daemon: &mut Daemon) {
+        self.events.set_nonblocking(true).unwrap();
+        let started = Instant::now();
+        loop {
+            match self.events.accept() {
+                // connections closed right away only check that Hyprland is alive
+                Ok((stream, _)) if is_closed(&stream) => continue,
+                Ok((stream, _)) => {
+                    self.stream = Some(stream);
+                    break;
+                }
+                Err(_) if started.elapsed() < TIMEOUT => {
+                    if let Some(status) = daemon.try_wait() {
+                        panic!("daemon exited before connecting: {status}");
+                    }
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                Err(e) => panic!("daemon didn't connect to the event socket: {e}"),
+            }
+        }
+    }
+
+    // run a client command of the running daemon, returns exit status and output
+    pub fn ctl(&self, args: &[&str]) -> (bool, String) {
+        let output = Command::new(env!("CARGO_BIN_EXE_hyprland-per-window-layout"))
+            .args(args)
+            .env_clear()
+            .env("HOME", &self.root)
+            .env("HYPRLAND_PER_WINDOW_LAYOUT_INSTANCE", &self.instance)
+            .env("XDG_CONFIG_HOME", self.root.join("config"))
+            .output()
+            .unwrap();
+        (
+            output.status.success(),
+            String::from_utf8_lossy(&output.stdout).to_string(),
+        )
+    }
+
+    // send events like "activewindowv2>>5632c5a16e80"
+    pub fn send(&mut self, events: &[&str]) {
+        let stream = self.stream.as_mut().expect("daemon is not connected");
+        for event in events {
+            writeln!(stream, "{event}").unwrap();
+        }
+    }
+
+    // open a window and focus it
+    pub fn open_window(&mut self, addr: &str, class: &str, title: &str) {
+        self.send(&[
+            &format!("openwindow>>{addr},1,{class},{title}"),
+            &format!("activewindow>>{class},{title}"),
+            &format!("activewindowv2>>{addr}"),
+        ]);
+    }
+
+    pub fn focus(&mut self, addr: &str, class: &str, title: &str) {
+        self.send(&[
+            &format!("activewindow>>{class},{title}"),
+            &format!("activewindowv2>>{addr}"),
+        ]);
+    }
+
+    // user switched the layout with a key binding
+    pub fn switch_layout(&mut self, keyboard: &str, long_name: &str) {
+        self.send(&[&format!("activelayout>>{keyboard},{long_name}")]);
+    }
+
+    // wait until the daemon stops sending requests
+    // and return switchxkblayout commands received so far
+    pub fn switches(&self) -> Vec<String> {
+        let started = Instant::now();
+        loop {
+            std::thread::sleep(Duration::from_millis(50));
+            let server = self.server.lock().unwrap();
+            let idle = server
+                .last_request
+                .map(|last| last.elapsed() > IDLE)
+                .unwrap_or(true);
+            if (idle && started.elapsed() > IDLE) || started.elapsed() > TIMEOUT {
+                return switch_commands(&server.requests);
+            }
+        }
+    }
+}
+
+impl Drop for MockHyprland {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+// the daemon never writes to the event socket, so a read ends only when it is closed
+fn is_closed(mut stream: &UnixStream) -> bool {
+    stream.set_nonblocking(false).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    let closed = matches!(stream.read(&mut [0u8; 1]), Ok(0));
+    stream.set_read_timeout(None).unwrap();
+    closed
+}
+
+// answer requests on .socket.sock in a thread, returns the listener of .socket2.sock
+fn serve(socket_dir: &Path, server: Arc<Mutex<Server>>) -> UnixListener {
+    let requests = UnixListener::bind(socket_dir.join(".socket.sock")).unwrap();
+    std::thread::spawn(move || {
+        for stream in requests.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => break,
+            };
+            // the daemon writes one request and waits for the reply
+            let mut buf = [0u8; 4096];
+            let size = stream.read(&mut buf).unwrap_or(0);
+            let request = String::from_utf8_lossy(&buf[..size]).to_string();
+            let reply = server.lock().unwrap().reply(&request);
+            let _ = stream.write_all(reply.as_bytes());
+        }
+    });
+    UnixListener::bind(socket_dir.join(".socket2.sock")).unwrap()
+}
+
+// commands sent in batches, in order
+fn switch_commands(requests: &[String]) -> Vec<String> {
+    requests
+        .iter()
+        .filter_map(|request| request.strip_prefix("[[BATCH]]"))
+        .flat_map(|commands| commands.split(';'))
+        .filter(|command| command.starts_with("switchxkblayout "))
+        .map(|command| command.to_string())
+        .collect()
+}
+
+// running daemon, killed when dropped
+pub struct Daemon {
+    child: Option<Child>,
+}
+
+impl Daemon {
+    fn try_wait(&mut self) -> Option<std::process::ExitStatus> {
+        self.child.as_mut()?.try_wait().ok()?
+    }
+
+    // stop with SIGTERM, as a session manager would
+    pub fn stop(mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM);
+            let _ = child.wait();
+        }
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+pub fn read_state(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
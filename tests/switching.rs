@@ -0,0 +1,258 @@
+// end-to-end tests: the daemon talks to a fake Hyprland
+
+mod mock_hyprland;
+use mock_hyprland::{read_state, MockHyprland};
+
+#[test]
+fn restores_layout_of_each_window() {
+    let mut hypr = MockHyprland::start("restore");
+    let _daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "kitty", "~");
+    hypr.open_window("b2", "firefox", "Mozilla Firefox");
+    hypr.focus("a1", "kitty", "~");
+    hypr.switch_layout("kb1", "German");
+    hypr.focus("b2", "firefox", "Mozilla Firefox");
+    hypr.focus("a1", "kitty", "~");
+    assert_eq!(
+        hypr.switches(),
+        ["switchxkblayout kb1 0", "switchxkblayout kb1 1"]
+    );
+}
+
+#[test]
+fn default_layouts_for_new_windows() {
+    let mut hypr = MockHyprland::start("defaults");
+    hypr.write_options(
+        r#"
+keyboards = ["kb1"]
+
+[[default_layouts]]
+1 = ["org.telegram.desktop"]
+"#,
+    );
+    let _daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "org.telegram.desktop", "Telegram");
+    hypr.open_window("b2", "kitty", "~");
+    hypr.focus("a1", "org.telegram.desktop", "Telegram");
+    assert_eq!(
+        hypr.switches(),
+        [
+            "switchxkblayout kb1 1",
+            "switchxkblayout kb1 0",
+            "switchxkblayout kb1 1"
+        ]
+    );
+}
+
+#[test]
+fn learned_layout_survives_restart() {
+    let mut hypr = MockHyprland::start("persist");
+    hypr.write_options("keyboards = [\"kb1\"]\npersist = true");
+    let daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "firefox", "Mozilla Firefox");
+    hypr.switch_layout("kb1", "German");
+    assert!(hypr.switches().is_empty());
+    daemon.stop();
+    assert_eq!(read_state(&hypr.state_file())["classes"]["firefox"], 1);
+
+    let _daemon = hypr.spawn_daemon();
+    hypr.open_window("b2", "firefox", "Mozilla Firefox");
+    assert_eq!(hypr.switches(), ["switchxkblayout kb1 1"]);
+}
+
+#[test]
+fn rejected_keyboard_is_dropped() {
+    let mut hypr = MockHyprland::start("rejected");
+    hypr.write_options(
+        r#"
+keyboards = ["kb1", "kb2"]
+
+[[default_layouts]]
+1 = ["org.telegram.desktop"]
+"#,
+    );
+    // listed by Hyprland but can't be switched
+    hypr.set_reply(
+        "j/devices",
+        r#"{"keyboards": [{"name": "kb1", "active_keymap": "English (US)"}, {"name": "kb2"}]}"#,
+    );
+    hypr.reject_keyboard("kb2");
+    let _daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "org.telegram.desktop", "Telegram");
+    hypr.open_window("b2", "kitty", "~");
+    assert_eq!(
+        hypr.switches(),
+        [
+            "switchxkblayout kb1 1",
+            "switchxkblayout kb2 1",
+            "switchxkblayout kb1 0"
+        ]
+    );
+}
+
+#[test]
+fn focused_window_keeps_layout_at_start() {
+    let mut hypr = MockHyprland::start("focused");
+    hypr.set_reply(
+        "j/devices",
+        r#"{"keyboards": [{"name": "kb1", "active_keymap": "German"}]}"#,
+    );
+    hypr.set_reply(
+        "j/clients",
+        r#"[{"address": "0xa1", "class": "kitty", "title": "~", "focusHistoryID": 1},
+            {"address": "0xb2", "class": "kitty", "title": "~", "focusHistoryID": 0}]"#,
+    );
+    let _daemon = hypr.spawn_daemon();
+    hypr.focus("b2", "kitty", "~");
+    hypr.focus("a1", "kitty", "~");
+    hypr.focus("b2", "kitty", "~");
+    assert_eq!(
+        hypr.switches(),
+        ["switchxkblayout kb1 0", "switchxkblayout kb1 1"]
+    );
+}
+
+#[test]
+fn rules_match_windows_opened_before_start() {
+    let mut hypr = MockHyprland::start("clients");
+    hypr.write_options(
+        r#"
+keyboards = ["kb1"]
+
+[[rules]]
+initial_title = "^Private Browsing$"
+layout = "de"
+"#,
+    );
+    hypr.set_reply(
+        "j/clients",
+        r#"[{"address": "0xa1", "class": "firefox", "title": "Example",
+             "initialClass": "firefox", "initialTitle": "Private Browsing"}]"#,
+    );
+    let _daemon = hypr.spawn_daemon();
+    hypr.focus("a1", "firefox", "Example");
+    assert_eq!(hypr.switches(), ["switchxkblayout kb1 1"]);
+}
+
+#[test]
+fn plugged_keyboards_are_discovered() {
+    let mut hypr = MockHyprland::start("discovery");
+    hypr.set_reply(
+        "j/devices",
+        r#"{"keyboards": [{"name": "kb1", "active_keymap": "English (US)"}, {"name": "kb2"}]}"#,
+    );
+    let _daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "kitty", "~");
+    hypr.switch_layout("kb1", "German");
+    hypr.open_window("b2", "firefox", "Mozilla Firefox");
+    assert_eq!(
+        hypr.switches(),
+        ["switchxkblayout kb1 0", "switchxkblayout kb2 0"]
+    );
+    // kb1 unplugged, devices are listed again after config reload
+    hypr.set_reply(
+        "j/devices",
+        r#"{"keyboards": [{"name": "kb2", "active_keymap": "English (US)"}]}"#,
+    );
+    hypr.send(&["configreloaded>>"]);
+    hypr.focus("a1", "kitty", "~");
+    assert_eq!(
+        hypr.switches(),
+        [
+            "switchxkblayout kb1 0",
+            "switchxkblayout kb2 0",
+            "switchxkblayout kb2 1"
+        ]
+    );
+}
+
+#[test]
+fn control_socket_serves_the_same_user() {
+    let mut hypr = MockHyprland::start("control");
+    let _daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "kitty", "~");
+    hypr.switches();
+    let (ok, output) = hypr.ctl(&["status"]);
+    assert!(ok, "{output}");
+    assert!(output.contains("0xa1"), "{output}");
+}
+
+#[test]
+fn broken_options_are_not_loaded_on_reconnect() {
+    let mut hypr = MockHyprland::start("broken-options");
+    hypr.write_options(
+        r#"
+keyboards = ["kb1"]
+
+[[default_layouts]]
+1 = ["org.telegram.desktop"]
+"#,
+    );
+    let mut daemon = hypr.spawn_daemon();
+    hypr.write_options("[[default_layouts");
+    hypr.reconnect(&mut daemon);
+    hypr.open_window("a1", "org.telegram.desktop", "Telegram");
+    assert_eq!(hypr.switches(), ["switchxkblayout kb1 1"]);
+}
+
+#[test]
+fn closed_window_address_gets_a_fresh_layout() {
+    let mut hypr = MockHyprland::start("close");
+    let _daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "kitty", "~");
+    hypr.switch_layout("kb1", "German");
+    hypr.open_window("b2", "firefox", "Mozilla Firefox");
+    hypr.focus("a1", "kitty", "~");
+    hypr.send(&["closewindow>>a1"]);
+    // Hyprland may reuse the address for a new window
+    hypr.open_window("a1", "foot", "~");
+    assert_eq!(
+        hypr.switches(),
+        [
+            "switchxkblayout kb1 0",
+            "switchxkblayout kb1 1",
+            "switchxkblayout kb1 0"
+        ]
+    );
+}
+
+#[test]
+fn focused_window_is_synced_after_reconnect() {
+    let mut hypr = MockHyprland::start("reconnect");
+    let mut daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "kitty", "~");
+    hypr.switch_layout("kb1", "German");
+    hypr.open_window("b2", "firefox", "Mozilla Firefox");
+    assert_eq!(hypr.switches(), ["switchxkblayout kb1 0"]);
+    // the focus moved while the daemon was disconnected
+    hypr.set_reply(
+        "j/clients",
+        r#"[
+            {"address": "0xa1", "class": "kitty", "title": "~", "focusHistoryID": 0},
+            {"address": "0xb2", "class": "firefox", "title": "Mozilla Firefox", "focusHistoryID": 1}
+        ]"#,
+    );
+    hypr.reconnect(&mut daemon);
+    assert_eq!(
+        hypr.switches(),
+        ["switchxkblayout kb1 0", "switchxkblayout kb1 1"]
+    );
+}
+
+#[test]
+fn new_instance_is_found_after_crash() {
+    let mut hypr = MockHyprland::start("crash");
+    hypr.write_options(
+        r#"
+keyboards = ["kb1"]
+
+[[default_layouts]]
+1 = ["org.telegram.desktop"]
+"#,
+    );
+    let mut daemon = hypr.spawn_daemon();
+    hypr.open_window("a1", "kitty", "~");
+    hypr.restart(&mut daemon, "mock-restarted");
+    hypr.open_window("b2", "org.telegram.desktop", "Telegram");
+    assert_eq!(hypr.switches(), ["switchxkblayout kb1 1"]);
+}